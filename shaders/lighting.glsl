@@ -0,0 +1,22 @@
+// Shared lighting functions, include with #include "lighting.glsl"
+
+#include "uniforms.glsl"
+
+vec3 diffuse(vec3 normal)
+{
+    vec3 light = ambient.rgb;
+    for (int i = 0; i < lightCount; i++) {
+        light += lights[i].color.rgb * max(dot(normal, -lights[i].direction.xyz), 0.0);
+    }
+    return light;
+}
+
+#define FOG_COLOR vec3(0.2, 0.3, 0.3) // Same as the clear color
+#define FOG_DENSITY 0.002
+
+// Exponential squared fog, fading towards the clear color
+vec3 fog(vec3 color, float distance)
+{
+    float amount = 1.0 - exp(-pow(distance * FOG_DENSITY, 2.0));
+    return mix(color, FOG_COLOR, amount);
+}
@@ -1,17 +1,43 @@
 #version 430 core
 in vec3 newNormals;
 in vec4 newColor;
+in vec3 newPosition;
 out vec4 frag_color;
 
+// Feature options, injected by ShaderLibrary. The defaults apply when compiled without it
+#ifndef LIT
+#define LIT 1
+#endif
+#ifndef FOG
+#define FOG 0
+#endif
+#ifndef DEBUG_VIEW
+#define DEBUG_VIEW 0 // 0: shaded, 1: normals, 2: vertex colors
+#endif
+
+#include "lighting.glsl"
+
 
 
 
 void main()
 {
-    vec3 lightDirection = normalize(vec3(0.8, -0.5, 0.6));
-    float diff = max(dot(newNormals, -lightDirection), 0.0);
-    vec4 normal_vec = newColor * diff;
+#if DEBUG_VIEW == 1
+    frag_color = vec4(normalize(newNormals) * 0.5 + 0.5, 1.0);
+#elif DEBUG_VIEW == 2
+    frag_color = vec4(newColor.rgb, 1.0);
+#else
+#if LIT
+    vec3 diff = diffuse(normalize(newNormals));
+#else
+    vec3 diff = vec3(1.0);
+#endif
+    vec4 normal_vec = newColor * vec4(diff, 1.0);
     normal_vec[3] = 1.0;
+#if FOG
+    normal_vec.rgb = fog(normal_vec.rgb, distance(newPosition, cameraPosition));
+#endif
     frag_color = normal_vec;
+#endif
     //frag_color = vec4(normal_light, 1.0);
 }
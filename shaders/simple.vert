@@ -1,11 +1,15 @@
 #version 430 core
 
+#include "uniforms.glsl"
+
 layout (location = 0) in vec3 position;
 layout (location = 1) in vec4 color;
-layout (location = 2) uniform mat4 transformation;
+layout (location = 2) uniform mat4 model;
+layout (location = 3) uniform vec4 tint;
 layout (location = 5) in vec3 normals;
 out vec4 newColor;
 out vec3 newNormals;
+out vec3 newPosition;
 //Task 4
 //uniform mat4 transformation;
 
@@ -13,9 +17,11 @@ out vec3 newNormals;
 
 void main()
 {
-    gl_Position = transformation * vec4(position, 1.0f);
-    newColor = color;
-    newNormals = normals;
+    vec4 world_position = model * vec4(position, 1.0f);
+    gl_Position = projection * view * world_position;
+    newPosition = world_position.xyz;
+    newColor = color * tint;
+    newNormals = mat3(model) * normals;
 
     //Task 2d
     //gl_Position = vec4(position.x*-1, position.y*-1, position.z, 1.0f);
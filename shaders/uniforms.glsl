@@ -0,0 +1,21 @@
+// Uniform blocks shared by every program, mirrored in src/uniform_buffer.rs
+
+#define MAX_LIGHTS 8
+
+layout (std140, binding = 0) uniform Frame {
+    mat4 view;
+    mat4 projection;
+    vec3 cameraPosition;
+    float time;
+};
+
+struct Light {
+    vec4 direction;
+    vec4 color;
+};
+
+layout (std140, binding = 1) uniform Lights {
+    vec4 ambient;
+    int lightCount;
+    Light lights[MAX_LIGHTS];
+};
@@ -0,0 +1,531 @@
+extern crate nalgebra_glm as glm;
+
+use crate::scene_graph::SceneNode;
+
+// Which camera the view is seen through. Cycled through with a key.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CameraMode {
+    FreeFly,
+    Orbit,
+    Chase,
+}
+
+// A perspective projection. `far` may be infinite, which keeps the far plane from cutting anything
+// off. Reverse-Z puts the near plane at depth 1 and the far plane at 0, which spreads the precision of
+// a floating point depth buffer evenly over the distance instead of bunching it up near the camera.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Perspective {
+    pub fov       : f32, // Vertical, in radians
+    pub near      : f32,
+    pub far       : f32,
+    pub reverse_z : bool,
+}
+
+// How a camera's view is projected onto the screen
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Projection {
+    Perspective(Perspective),
+    Orthographic {
+        height : f32, // How much of the world fits on screen from bottom to top
+        near   : f32,
+        far    : f32,
+    },
+}
+
+// Orientations for orthographic views: straight down like a map, or the classic isometric angle
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ViewPreset {
+    TopDown,
+    Isometric,
+}
+
+// The direction yaw and pitch look in, down the negative z-axis when both are zero
+fn direction(yaw: f32, pitch: f32) -> glm::Vec3 {
+    glm::vec3(pitch.cos() * yaw.sin(), pitch.sin(), -pitch.cos() * yaw.cos())
+}
+
+impl Default for Perspective {
+    fn default() -> Self {
+        Perspective { fov: 0.5, near: 1.0, far: 1000.0, reverse_z: false }
+    }
+}
+
+impl Perspective {
+    // An orthographic projection showing as much as this does at `distance` from the camera. It reaches as
+    // far behind the camera as in front, since how close the camera is doesn't change what it sees.
+    pub fn matching_orthographic(&self, distance: f32) -> Projection {
+        let height = 2.0 * distance * (self.fov / 2.0).tan();
+        let far = self.far.min(10000.0);
+        Projection::Orthographic { height, near: -far, far }
+    }
+}
+
+impl Default for Projection {
+    fn default() -> Self {
+        Projection::Perspective(Perspective::default())
+    }
+}
+
+impl Projection {
+    // The projection matrix for a viewport `aspect` times as wide as it is tall
+    pub fn matrix(&self, aspect: f32) -> glm::Mat4 {
+        match *self {
+            Projection::Perspective(Perspective { fov, near, far, reverse_z }) => match (reverse_z, far.is_finite()) {
+                (false, true) => glm::perspective(aspect, fov, near, far),
+                (false, false) => glm::infinite_perspective_rh_no(aspect, fov, near),
+                (true, true) => glm::reversed_perspective_rh_zo(aspect, fov, near, far),
+                (true, false) => glm::reversed_infinite_perspective_rh_zo(aspect, fov, near),
+            },
+            Projection::Orthographic { height, near, far } => {
+                let (half_width, half_height) = (height * aspect / 2.0, height / 2.0);
+                glm::ortho(-half_width, half_width, -half_height, half_height, near, far)
+            },
+        }
+    }
+
+    // Whether depth has to be cleared to 0 and tested with GREATER
+    pub fn reverse_z(&self) -> bool {
+        matches!(self, Projection::Perspective(Perspective { reverse_z: true, .. }))
+    }
+}
+
+impl ViewPreset {
+    // Yaw and pitch in radians, as for the cameras
+    pub fn yaw_pitch(self) -> (f32, f32) {
+        match self {
+            ViewPreset::TopDown => (0.0, -std::f32::consts::FRAC_PI_2),
+            // Looking down the diagonal, so the three axes are equally foreshortened
+            ViewPreset::Isometric => (std::f32::consts::FRAC_PI_4, -(1.0 / 2f32.sqrt()).atan()),
+        }
+    }
+}
+
+// A camera flying freely through the scene, turned by yaw and pitch. At zero yaw and pitch it
+// looks down the negative z-axis.
+pub struct Camera {
+    pub position    : glm::Vec3,
+    pub yaw         : f32, // Radians, turning right is positive
+    pub pitch       : f32, // Radians, looking up is positive
+    pub sensitivity : f32, // Radians turned per pixel the mouse moves
+    pub turn_speed  : f32, // Radians turned per second when turning with keys
+    pub speed       : f32, // Units moved per second
+    pub max_pitch   : f32, // How far up or down the camera may look, short of straight up, where yaw breaks down
+    pub projection  : Projection,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Camera {
+            position    : glm::zero(),
+            yaw         : 0.0,
+            pitch       : 0.0,
+            sensitivity : 0.003,
+            turn_speed  : 1.0,
+            speed       : 50.0,
+            max_pitch   : 89f32.to_radians(),
+            projection  : Projection::default(),
+        }
+    }
+}
+
+impl Camera {
+    // The direction the camera looks in
+    pub fn forward(&self) -> glm::Vec3 {
+        direction(self.yaw, self.pitch)
+    }
+
+    // Horizontal, to the right of where the camera looks
+    pub fn right(&self) -> glm::Vec3 {
+        glm::vec3(self.yaw.cos(), 0.0, self.yaw.sin())
+    }
+
+    // Turn by a mouse movement in pixels, with y pointing down like on screen
+    pub fn look(&mut self, mouse_delta: (f32, f32)) {
+        self.turn(mouse_delta.0 * self.sensitivity, -mouse_delta.1 * self.sensitivity);
+    }
+
+    // Turn by the given angles in radians
+    pub fn turn(&mut self, yaw: f32, pitch: f32) {
+        self.yaw = (self.yaw + yaw) % std::f32::consts::TAU;
+        self.pitch = (self.pitch + pitch).clamp(-self.max_pitch, self.max_pitch);
+    }
+
+    // Move `delta_time` seconds worth relative to the view: x to the right, y up along the world's
+    // y-axis and z forward, each in [-1, 1]
+    pub fn fly(&mut self, direction: &glm::Vec3, delta_time: f32) {
+        let movement = self.right() * direction.x + glm::vec3(0.0, direction.y, 0.0) + self.forward() * direction.z;
+        if movement.norm() > 0.0 {
+            // Moving diagonally is no faster than moving straight
+            let length = movement.norm().min(1.0);
+            self.position += movement.normalize() * length * self.speed * delta_time;
+        }
+    }
+
+    pub fn view(&self) -> glm::Mat4 {
+        glm::look_at(&self.position, &(self.position + self.forward()), &glm::vec3(0.0, 1.0, 0.0))
+    }
+}
+
+// Circles a target point, looking at it. The mouse turns around the target, the scroll wheel
+// zooms and the movement keys pan the target.
+pub struct OrbitCamera {
+    pub target       : glm::Vec3,
+    pub distance     : f32,
+    pub yaw          : f32, // Radians, the direction the camera looks in as for `Camera`
+    pub pitch        : f32,
+    pub sensitivity  : f32, // Radians turned per pixel the mouse moves
+    pub zoom_factor  : f32, // How much closer one step of the scroll wheel moves
+    pub pan_speed    : f32, // Distances panned per second, so panning keeps pace when zoomed out
+    pub min_distance : f32,
+    pub max_distance : f32,
+    pub max_pitch    : f32,
+    pub projection   : Projection,
+}
+
+// Follows a scene node from behind and above, as if on a spring
+pub struct ChaseCamera {
+    pub offset     : glm::Vec3, // Where to be relative to the node, turned with the node's heading
+    pub stiffness  : f32,       // How hard the spring pulls towards where the camera should be
+    pub damping    : f32,       // How much of the velocity is lost per second. 2 * sqrt(stiffness) is critically damped
+    pub clearance  : f32,       // How far above the terrain the camera is kept
+    pub zoom_factor: f32,       // How much the offset shrinks per step of the scroll wheel
+    pub projection : Projection,
+    position       : Option<glm::Vec3>, // None until the first update, which puts the camera right where it should be
+    velocity       : glm::Vec3,
+    target         : glm::Vec3,
+}
+
+impl CameraMode {
+    pub fn next(self) -> CameraMode {
+        match self {
+            CameraMode::FreeFly => CameraMode::Orbit,
+            CameraMode::Orbit => CameraMode::Chase,
+            CameraMode::Chase => CameraMode::FreeFly,
+        }
+    }
+}
+
+impl Default for OrbitCamera {
+    fn default() -> Self {
+        OrbitCamera {
+            target       : glm::zero(),
+            distance     : 50.0,
+            yaw          : 0.0,
+            pitch        : -0.4,
+            sensitivity  : 0.005,
+            zoom_factor  : 1.1,
+            pan_speed    : 1.0,
+            min_distance : 2.0,
+            max_distance : 1000.0,
+            max_pitch    : 89f32.to_radians(),
+            projection   : Projection::default(),
+        }
+    }
+}
+
+impl OrbitCamera {
+    pub fn position(&self) -> glm::Vec3 {
+        self.target - direction(self.yaw, self.pitch) * self.distance
+    }
+
+    // Turn around the target by a mouse movement in pixels, with y pointing down like on screen
+    pub fn look(&mut self, mouse_delta: (f32, f32)) {
+        self.yaw = (self.yaw + mouse_delta.0 * self.sensitivity) % std::f32::consts::TAU;
+        self.pitch = (self.pitch - mouse_delta.1 * self.sensitivity).clamp(-self.max_pitch, self.max_pitch);
+    }
+
+    // Move closer by a number of scroll wheel steps, or further away if negative
+    pub fn zoom(&mut self, steps: f32) {
+        let distance = (self.distance / self.zoom_factor.powf(steps)).clamp(self.min_distance, self.max_distance);
+        // Orthographic views don't get smaller with distance, so they are zoomed by showing less
+        if let Projection::Orthographic { height, .. } = &mut self.projection {
+            *height *= distance / self.distance;
+        }
+        self.distance = distance;
+    }
+
+    // Look at the target from the preset's angle through an orthographic projection showing about as much
+    // as `perspective` does
+    pub fn set_preset(&mut self, preset: ViewPreset, perspective: &Perspective) {
+        let (yaw, pitch) = preset.yaw_pitch();
+        self.yaw = yaw;
+        self.pitch = pitch;
+        self.projection = match self.projection {
+            Projection::Perspective(_) => perspective.matching_orthographic(self.distance),
+            orthographic => orthographic,
+        };
+    }
+
+    // Move the target `delta_time` seconds worth: x to the right, y up and z forward along the ground
+    pub fn pan(&mut self, direction: &glm::Vec3, delta_time: f32) {
+        let right = glm::vec3(self.yaw.cos(), 0.0, self.yaw.sin());
+        let forward = glm::vec3(self.yaw.sin(), 0.0, -self.yaw.cos());
+        let movement = right * direction.x + glm::vec3(0.0, direction.y, 0.0) + forward * direction.z;
+        self.target += movement * self.pan_speed * self.distance * delta_time;
+    }
+
+    pub fn view(&self) -> glm::Mat4 {
+        // Up is square to the view, so looking straight down works too
+        let up = direction(self.yaw, self.pitch + std::f32::consts::FRAC_PI_2);
+        glm::look_at(&self.position(), &self.target, &up)
+    }
+}
+
+impl Default for ChaseCamera {
+    fn default() -> Self {
+        ChaseCamera {
+            offset      : glm::vec3(0.0, 6.0, 20.0), // The helicopter's nose points down the negative z-axis
+            stiffness   : 16.0,
+            damping     : 8.0,
+            clearance   : 2.0,
+            zoom_factor : 1.1,
+            projection  : Projection::default(),
+            position    : None,
+            velocity    : glm::zero(),
+            target      : glm::zero(),
+        }
+    }
+}
+
+impl ChaseCamera {
+    pub fn position(&self) -> glm::Vec3 {
+        self.position.unwrap_or_else(glm::zero)
+    }
+
+    // Move closer to the node by a number of scroll wheel steps, or further away if negative
+    pub fn zoom(&mut self, steps: f32) {
+        let scale = self.zoom_factor.powf(steps);
+        self.offset /= scale;
+        // Orthographic views don't get smaller with distance, so they are zoomed by showing less
+        if let Projection::Orthographic { height, .. } = &mut self.projection {
+            *height /= scale;
+        }
+    }
+
+    // Spring towards the node's current world transform. Only the node's heading turns the offset,
+    // so the camera doesn't roll and pitch along with it. `terrain_height` gives the height of the
+    // ground at an x and z, if there is any.
+    pub fn update<F: Fn(f32, f32) -> Option<f32>>(&mut self, node: &SceneNode, terrain_height: F, delta_time: f32) {
+        let transform = &node.current_transformation_matrix;
+        self.target = (transform * glm::vec4(0.0, 0.0, 0.0, 1.0)).xyz();
+        let back = (transform * glm::vec4(0.0, 0.0, 1.0, 0.0)).xyz();
+        let back = glm::vec3(back.x, 0.0, back.z);
+        let back = if back.norm() > 1e-4 { back.normalize() } else { glm::vec3(0.0, 0.0, 1.0) };
+        let right = glm::vec3(back.z, 0.0, -back.x);
+        let wanted = self.target + right * self.offset.x + glm::vec3(0.0, self.offset.y, 0.0) + back * self.offset.z;
+
+        let mut position = match self.position {
+            Some(position) => position,
+            None => wanted,
+        };
+        // Small steps keep the spring stable when a frame takes long
+        let steps = (delta_time / (1.0 / 120.0)).ceil().max(1.0) as u32;
+        let step = delta_time / steps as f32;
+        for _ in 0..steps {
+            let acceleration = (wanted - position) * self.stiffness - self.velocity * self.damping;
+            self.velocity += acceleration * step;
+            position += self.velocity * step;
+        }
+
+        if let Some(ground) = terrain_height(position.x, position.z) {
+            if position.y < ground + self.clearance {
+                position.y = ground + self.clearance;
+                self.velocity.y = self.velocity.y.max(0.0);
+            }
+        }
+        self.position = Some(position);
+    }
+
+    pub fn view(&self) -> glm::Mat4 {
+        glm::look_at(&self.position(), &self.target, &glm::vec3(0.0, 1.0, 0.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    fn assert_near(actual: &glm::Vec3, expected: &glm::Vec3) {
+        assert!(glm::distance(actual, expected) < 1e-3, "{:?} should be {:?}", actual, expected);
+    }
+
+    // Where a point in view space ends up in normalized device coordinates
+    fn ndc(projection: &Projection, point: glm::Vec3) -> glm::Vec3 {
+        let clip = projection.matrix(1.0) * glm::vec4(point.x, point.y, point.z, 1.0);
+        clip.xyz() / clip.w
+    }
+
+    fn ndc_depth(projection: &Projection, distance: f32) -> f32 {
+        ndc(projection, glm::vec3(0.0, 0.0, -distance)).z
+    }
+
+    #[test]
+    fn turning_clamps_pitch_and_wraps_yaw() {
+        let mut camera = Camera::default();
+        camera.turn(0.0, 10.0);
+        assert_eq!(camera.pitch, camera.max_pitch);
+        camera.turn(0.0, -20.0);
+        assert_eq!(camera.pitch, -camera.max_pitch);
+        camera.turn(std::f32::consts::TAU + 0.5, 0.0);
+        assert!((camera.yaw - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn flies_relative_to_the_view() {
+        // Looking down the positive x-axis and a little upwards
+        let mut camera = Camera { yaw: FRAC_PI_2, pitch: 0.3, speed: 10.0, ..Default::default() };
+        camera.fly(&glm::vec3(0.0, 0.0, 1.0), 0.5);
+        assert_near(&camera.position, &(camera.forward() * 5.0));
+        assert!(camera.position.x > 0.0 && camera.position.y > 0.0);
+
+        // Right is level whatever the pitch, and up is the world's
+        camera.position = glm::zero();
+        camera.fly(&glm::vec3(1.0, 0.0, 0.0), 0.5);
+        assert_near(&camera.position, &glm::vec3(0.0, 0.0, 5.0));
+        camera.position = glm::zero();
+        camera.fly(&glm::vec3(0.0, 1.0, 0.0), 0.5);
+        assert_near(&camera.position, &glm::vec3(0.0, 5.0, 0.0));
+
+        // Diagonally no faster than straight
+        camera.position = glm::zero();
+        camera.fly(&glm::vec3(1.0, 0.0, 1.0), 0.5);
+        assert!((camera.position.norm() - 5.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn orbit_zoom_stays_within_limits() {
+        let mut camera = OrbitCamera::default();
+        camera.zoom(1.0);
+        assert!((camera.distance - 50.0 / 1.1).abs() < 1e-4);
+        camera.zoom(100.0);
+        assert_eq!(camera.distance, camera.min_distance);
+        camera.zoom(-1000.0);
+        assert_eq!(camera.distance, camera.max_distance);
+    }
+
+    #[test]
+    fn orbit_zoom_scales_orthographic_views() {
+        let mut camera = OrbitCamera { distance: 10.0, ..Default::default() };
+        camera.projection = Projection::Orthographic { height: 20.0, near: -100.0, far: 100.0 };
+        camera.zoom(-1.0);
+        assert!(matches!(camera.projection, Projection::Orthographic { height, .. } if (height - 22.0).abs() < 1e-4));
+        // Zooming past the limit shows no less than at the limit
+        camera.zoom(100.0);
+        let expected = 20.0 * camera.min_distance / 10.0;
+        assert!(matches!(camera.projection, Projection::Orthographic { height, .. } if (height - expected).abs() < 1e-4));
+    }
+
+    #[test]
+    fn orbit_pans_along_the_ground_at_the_pace_of_the_distance() {
+        let mut camera = OrbitCamera { yaw: FRAC_PI_2, pitch: -1.0, distance: 20.0, ..Default::default() };
+        camera.pan(&glm::vec3(0.0, 0.0, 1.0), 0.5);
+        assert_near(&camera.target, &glm::vec3(10.0, 0.0, 0.0));
+        camera.pan(&glm::vec3(1.0, 0.0, 0.0), 0.5);
+        assert_near(&camera.target, &glm::vec3(10.0, 0.0, 10.0));
+    }
+
+    #[test]
+    fn chase_starts_behind_the_node_and_turns_with_its_heading() {
+        let mut node = SceneNode::new();
+        node.current_transformation_matrix = glm::translation(&glm::vec3(5.0, 1.0, 0.0)) * glm::rotation(FRAC_PI_2, &glm::vec3(0.0, 1.0, 0.0));
+        let mut camera = ChaseCamera::default();
+        camera.update(&node, |_, _| None, 0.016);
+        // Facing down the negative x-axis after the turn, so behind is towards positive x
+        assert_near(&camera.position(), &glm::vec3(25.0, 7.0, 0.0));
+    }
+
+    #[test]
+    fn chase_springs_towards_the_node_without_overshooting() {
+        let mut node = SceneNode::new();
+        let mut camera = ChaseCamera::default();
+        camera.update(&node, |_, _| None, 0.016);
+
+        node.current_transformation_matrix = glm::translation(&glm::vec3(0.0, 0.0, -50.0));
+        let start = camera.position();
+        for _ in 0..300 {
+            camera.update(&node, |_, _| None, 1.0 / 60.0);
+            // Critically damped by default, so it never goes past where it should be
+            assert!(camera.position().z >= -30.0 - 1e-3, "{:?}", camera.position());
+        }
+        assert_near(&camera.position(), &glm::vec3(0.0, 6.0, -30.0));
+        assert_near(&start, &glm::vec3(0.0, 6.0, 20.0));
+
+        // One long frame ends up in the same place as many short ones
+        let mut long = ChaseCamera::default();
+        long.update(&SceneNode::new(), |_, _| None, 0.016);
+        long.update(&node, |_, _| None, 5.0);
+        assert_near(&long.position(), &camera.position());
+    }
+
+    #[test]
+    fn chase_keeps_above_the_terrain() {
+        let node = SceneNode::new();
+        let mut camera = ChaseCamera::default();
+        for _ in 0..10 {
+            camera.update(&node, |x, z| Some(10.0 + 0.01 * (x + z)), 0.1);
+            let position = camera.position();
+            assert!(position.y >= 10.0 + 0.01 * (position.x + position.z) + camera.clearance - 1e-4, "{:?}", position);
+        }
+    }
+
+    #[test]
+    fn chase_zoom_scales_the_offset_and_orthographic_views() {
+        let mut camera = ChaseCamera { projection: Projection::Orthographic { height: 22.0, near: -100.0, far: 100.0 }, ..Default::default() };
+        camera.zoom(1.0);
+        assert_near(&camera.offset, &(glm::vec3(0.0, 6.0, 20.0) / 1.1));
+        assert!(matches!(camera.projection, Projection::Orthographic { height, .. } if (height - 20.0).abs() < 1e-4));
+    }
+
+    #[test]
+    fn perspective_depth_runs_from_near_to_far() {
+        let perspective = Perspective { fov: 0.5, near: 1.0, far: 100.0, reverse_z: false };
+        let projection = Projection::Perspective(perspective);
+        assert!((ndc_depth(&projection, 1.0) + 1.0).abs() < 1e-5);
+        assert!((ndc_depth(&projection, 100.0) - 1.0).abs() < 1e-5);
+        assert!(!projection.reverse_z());
+
+        let infinite = Projection::Perspective(Perspective { far: f32::INFINITY, ..perspective });
+        assert!((ndc_depth(&infinite, 1.0) + 1.0).abs() < 1e-5);
+        let far_away = ndc_depth(&infinite, 1e6);
+        assert!(far_away < 1.0 && far_away > 0.999, "{}", far_away);
+    }
+
+    #[test]
+    fn reverse_z_puts_near_at_one_and_far_at_zero() {
+        let perspective = Perspective { fov: 0.5, near: 1.0, far: 100.0, reverse_z: true };
+        let projection = Projection::Perspective(perspective);
+        assert!((ndc_depth(&projection, 1.0) - 1.0).abs() < 1e-5);
+        assert!(ndc_depth(&projection, 100.0).abs() < 1e-5);
+        assert!(projection.reverse_z());
+
+        let infinite = Projection::Perspective(Perspective { far: f32::INFINITY, ..perspective });
+        assert!((ndc_depth(&infinite, 1.0) - 1.0).abs() < 1e-5);
+        let far_away = ndc_depth(&infinite, 1e6);
+        assert!(far_away > 0.0 && far_away < 1e-5, "{}", far_away);
+        assert!(ndc_depth(&infinite, 10.0) > ndc_depth(&infinite, 20.0));
+    }
+
+    #[test]
+    fn orthographic_fits_its_height() {
+        let projection = Projection::Orthographic { height: 10.0, near: -50.0, far: 50.0 };
+        assert_near(&ndc(&projection, glm::vec3(5.0, 5.0, 0.0)), &glm::vec3(1.0, 1.0, 0.0));
+        assert!(!projection.reverse_z());
+    }
+
+    #[test]
+    fn matching_orthographic_shows_as_much_at_the_distance() {
+        let perspective = Perspective { far: f32::INFINITY, ..Default::default() };
+        let orthographic = perspective.matching_orthographic(40.0);
+        assert!(matches!(orthographic, Projection::Orthographic { near, far, .. } if near == -10000.0 && far == 10000.0));
+
+        // A point at the distance lands in the same place on screen through both
+        let point = glm::vec3(3.0, 4.0, -40.0);
+        let through_perspective = ndc(&Projection::Perspective(perspective), point);
+        let through_orthographic = ndc(&orthographic, point);
+        assert!((through_perspective.xy() - through_orthographic.xy()).norm() < 1e-4);
+
+        let orthographic = Perspective::default().matching_orthographic(40.0);
+        assert!(matches!(orthographic, Projection::Orthographic { near, far, .. } if near == -1000.0 && far == 1000.0));
+    }
+}
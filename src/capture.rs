@@ -0,0 +1,175 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::gl_object::Buffer;
+
+// How many readbacks may be in flight before capturing waits for the oldest one
+const MAX_IN_FLIGHT: usize = 3;
+// How many frames may wait for the encoder before the render thread is held back
+const MAX_QUEUED: usize = 8;
+
+// A frame read into a pixel pack buffer, waiting for the GPU to finish writing it
+struct Readback {
+    buffer : Buffer,
+    fence  : gl::types::GLsync,
+    path   : PathBuf,
+    width  : u32,
+    height : u32,
+}
+
+struct EncodeJob {
+    path   : PathBuf,
+    width  : u32,
+    height : u32,
+    pixels : Vec<u8>,
+}
+
+// Saves frames from the bound read framebuffer as PNGs without stalling the render thread.
+// Frames are copied into pixel pack buffers asynchronously, mapped once their fence has been
+// passed, and encoded on a worker thread.
+pub struct FrameCapture {
+    free    : Vec<Buffer>,
+    pending : VecDeque<Readback>,
+    jobs    : Option<mpsc::SyncSender<EncodeJob>>,
+    worker  : Option<thread::JoinHandle<()>>,
+}
+
+// Numbered frames at a fixed timestep, for turning into a video
+pub struct Recording {
+    pub dir      : PathBuf,
+    pub timestep : f32,
+    frame        : u32,
+}
+
+fn encode(job: EncodeJob) {
+    if let Some(dir) = job.path.parent() {
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            eprintln!("Could not create {}: {}", dir.display(), e);
+            return;
+        }
+    }
+    let image = image::RgbImage::from_raw(job.width, job.height, job.pixels).unwrap();
+    // OpenGL puts the first row at the bottom
+    match image::imageops::flip_vertical(&image).save(&job.path) {
+        Ok(()) => println!("Saved {}", job.path.display()),
+        Err(e) => eprintln!("Could not save {}: {}", job.path.display(), e),
+    }
+}
+
+// The current UTC time as 2021-09-30_13-37-00.042, for file names
+pub fn timestamp() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = now.as_secs() as i64;
+    let (days, time) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+
+    // Days since 1970-01-01 to a civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    format!("{:04}-{:02}-{:02}_{:02}-{:02}-{:02}.{:03}", year, month, day, time / 3600, time / 60 % 60, time % 60, now.subsec_millis())
+}
+
+impl FrameCapture {
+    pub fn new() -> FrameCapture {
+        let (jobs, queue) = mpsc::sync_channel::<EncodeJob>(MAX_QUEUED);
+        // The worker lives until the capture, and with it the job sender, is dropped
+        let worker = thread::spawn(move || {
+            for job in queue {
+                encode(job);
+            }
+        });
+        FrameCapture { free: vec![], pending: VecDeque::new(), jobs: Some(jobs), worker: Some(worker) }
+    }
+
+    // Start copying the bound read framebuffer into an image, to be saved at `path`
+    pub unsafe fn capture(&mut self, path: &Path, width: u32, height: u32) {
+        if self.pending.len() >= MAX_IN_FLIGHT {
+            let oldest = self.pending.pop_front().unwrap();
+            self.complete(oldest);
+        }
+
+        let buffer = self.free.pop().unwrap_or_else(|| Buffer::new());
+        gl::BindBuffer(gl::PIXEL_PACK_BUFFER, buffer.id());
+        gl::BufferData(gl::PIXEL_PACK_BUFFER, (width * height * 3) as isize, std::ptr::null(), gl::STREAM_READ);
+        gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+        // With a pack buffer bound, the pointer is an offset into it and the call returns right away
+        gl::ReadPixels(0, 0, width as i32, height as i32, gl::RGB, gl::UNSIGNED_BYTE, std::ptr::null_mut());
+        gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+
+        let fence = gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+        self.pending.push_back(Readback { buffer, fence, path: path.to_path_buf(), width, height });
+    }
+
+    // Hand the frames that are done to the encoder. Call this once per frame.
+    pub unsafe fn poll(&mut self) {
+        while let Some(readback) = self.pending.front() {
+            let status = gl::ClientWaitSync(readback.fence, 0, 0);
+            if status != gl::ALREADY_SIGNALED && status != gl::CONDITION_SATISFIED {
+                break;
+            }
+            let readback = self.pending.pop_front().unwrap();
+            self.complete(readback);
+        }
+    }
+
+    // Wait for every capture to be read back
+    pub unsafe fn flush(&mut self) {
+        while let Some(readback) = self.pending.pop_front() {
+            self.complete(readback);
+        }
+    }
+
+    unsafe fn complete(&mut self, readback: Readback) {
+        while gl::ClientWaitSync(readback.fence, gl::SYNC_FLUSH_COMMANDS_BIT, 1_000_000_000) == gl::TIMEOUT_EXPIRED { }
+        gl::DeleteSync(readback.fence);
+
+        let size = (readback.width * readback.height * 3) as usize;
+        gl::BindBuffer(gl::PIXEL_PACK_BUFFER, readback.buffer.id());
+        let mapped = gl::MapBufferRange(gl::PIXEL_PACK_BUFFER, 0, size as isize, gl::MAP_READ_BIT) as *const u8;
+        let pixels = std::slice::from_raw_parts(mapped, size).to_vec();
+        gl::UnmapBuffer(gl::PIXEL_PACK_BUFFER);
+        gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+        self.free.push(readback.buffer);
+
+        let job = EncodeJob { path: readback.path, width: readback.width, height: readback.height, pixels };
+        if let Some(jobs) = &self.jobs {
+            // Blocks if the encoder has fallen behind, rather than piling up frames in memory
+            let _ = jobs.send(job);
+        }
+    }
+}
+
+// Finishes the captures still in flight, so has to be dropped on the thread owning the context
+impl Drop for FrameCapture {
+    fn drop(&mut self) {
+        unsafe { self.flush() };
+        self.jobs = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Recording {
+    // Record into a new timestamped directory under `parent`
+    pub fn new(parent: &Path, fps: f32) -> Recording {
+        Recording { dir: parent.join(timestamp()), timestep: 1.0 / fps, frame: 0 }
+    }
+
+    // Where the next frame goes
+    pub fn next_path(&mut self) -> PathBuf {
+        let path = self.dir.join(format!("frame_{:05}.png", self.frame));
+        self.frame += 1;
+        path
+    }
+}
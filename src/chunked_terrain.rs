@@ -0,0 +1,419 @@
+extern crate nalgebra_glm as glm;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, mpsc};
+use std::thread;
+
+use crate::mesh::{ColorMode, Mesh};
+use crate::scene_graph::{SceneNode, Node};
+use crate::shader_library::Material;
+use crate::vao::{Vao, VertexLayout};
+use crate::terrain::{Heightmap, NoiseParams, TerrainParams};
+
+// Where the heights of the terrain come from. Both are sampled in grid coordinates.
+#[allow(dead_code)]
+#[derive(Clone)]
+pub enum HeightSource {
+    Noise(NoiseParams),        // Unbounded, chunks are generated wherever the camera goes
+    Heightmap(Arc<Heightmap>), // Bounded by the size of the heightmap
+}
+
+#[derive(Clone)]
+pub struct ChunkedTerrainParams {
+    pub chunk_cells   : usize,    // Grid cells along each side of a chunk at full detail
+    pub lod_distances : Vec<f32>, // Chunks closer than lod_distances[i] use LOD i, the rest the coarsest LOD
+    pub view_distance : f32,      // Chunks further away than this are unloaded
+    pub max_pending   : usize,    // How many chunks may be queued on the background thread at once
+    pub terrain       : TerrainParams,
+}
+
+// A chunk at a given LOD. `edges` holds the LOD each side has to match (north, east, south, west),
+// which is the coarser of this chunk and its neighbour on that side. `coloring` counts the color modes set so far.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct ChunkKey {
+    x        : i32,
+    z        : i32,
+    lod      : u32,
+    edges    : [u32; 4],
+    coloring : u32,
+}
+
+struct LoadedChunk {
+    key  : ChunkKey,
+    _vao : Vao,  // Deleted when the chunk is unloaded
+    node : Node,
+}
+
+// Terrain split into square chunks, each a SceneNode with its own VAO. Chunk meshes are built on a
+// background thread and uploaded by `update`, which has to be called from the thread owning the GL context.
+pub struct ChunkedTerrain {
+    pub root     : Node,     // Add this to the scene graph, the chunks are added as its children
+    pub material : Material, // What the chunks are drawn with
+
+    source     : HeightSource,
+    params     : ChunkedTerrainParams,
+    color_mode : Option<Arc<ColorMode>>, // None colors by height with the terrain's color ramp
+    coloring   : u32,
+    chunks     : HashMap<(i32, i32), LoadedChunk>,
+    pending    : HashSet<ChunkKey>,
+    requests   : mpsc::Sender<(ChunkKey, Option<Arc<ColorMode>>)>,
+    results    : mpsc::Receiver<(ChunkKey, Mesh)>,
+}
+
+impl Default for ChunkedTerrainParams {
+    fn default() -> Self {
+        ChunkedTerrainParams {
+            chunk_cells   : 64,
+            lod_distances : vec![96.0, 192.0, 384.0],
+            view_distance : 640.0,
+            max_pending   : 8,
+            terrain       : TerrainParams {
+                skirt_depth : 2.0,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl HeightSource {
+    // Height in [0, 1] at fractional grid coordinates
+    fn sample(&self, x: f32, z: f32) -> f32 {
+        match self {
+            HeightSource::Noise(noise) => noise.sample(x, z),
+            HeightSource::Heightmap(heightmap) => heightmap.sample(x, z),
+        }
+    }
+
+    // Size of the terrain in grid cells, if it has one
+    fn extent(&self) -> Option<(usize, usize)> {
+        match self {
+            HeightSource::Noise(_) => None,
+            HeightSource::Heightmap(heightmap) => Some((heightmap.width - 1, heightmap.depth - 1)),
+        }
+    }
+}
+
+impl ChunkKey {
+    fn step(&self) -> usize {
+        1 << self.lod
+    }
+}
+
+// Build the mesh of a single chunk, centered on the origin
+fn build_chunk(source: &HeightSource, params: &ChunkedTerrainParams, key: ChunkKey, color_mode: Option<&ColorMode>) -> Mesh {
+    let step = key.step();
+    let n = params.chunk_cells / step;
+    let grid_x = (key.x * params.chunk_cells as i32) as f32;
+    let grid_z = (key.z * params.chunk_cells as i32) as f32;
+
+    // Sample one extra ring of points around the chunk so the normals along the edges match the neighbours
+    let mut heightmap = Heightmap::from_fn(n + 3, n + 3, |x, z| {
+        source.sample(
+            grid_x + (x as f32 - 1.0) * step as f32,
+            grid_z + (z as f32 - 1.0) * step as f32,
+        )
+    });
+
+    // Move the edge points facing a coarser neighbour onto the neighbour's edge, so no cracks open up between them
+    let edge_points: [Box<dyn Fn(usize) -> usize>; 4] = [
+        Box::new(|i| (n + 3) + 1 + i),           // North
+        Box::new(|i| (1 + i) * (n + 3) + 1 + n), // East
+        Box::new(|i| (1 + n) * (n + 3) + 1 + i), // South
+        Box::new(|i| (1 + i) * (n + 3) + 1),     // West
+    ];
+    for (side, index) in edge_points.iter().enumerate() {
+        if key.edges[side] <= key.lod {
+            continue;
+        }
+        let ratio = 1 << (key.edges[side] - key.lod);
+        for i in 0..=n {
+            let offset = i % ratio;
+            if offset != 0 {
+                let (a, b) = (heightmap.heights[index(i - offset)], heightmap.heights[index(i - offset + ratio)]);
+                heightmap.heights[index(i)] = a + (b - a) * offset as f32 / ratio as f32;
+            }
+        }
+    }
+
+    let mut terrain_params = TerrainParams {
+        cell_size: params.terrain.cell_size * step as f32,
+        ..params.terrain.clone()
+    };
+    // A chunk only has some of the terrain's heights, so they are colored by where they are on the whole terrain's
+    // scale instead of being scaled to the chunk like Mesh::recolor does
+    if let Some(ColorMode::Height(ramp)) = color_mode {
+        terrain_params.color_ramp = ramp.clone();
+    }
+    let mut mesh = heightmap.region_to_mesh(1, 1, n + 1, n + 1, &terrain_params);
+    match color_mode {
+        None | Some(ColorMode::Height(_)) => { },
+        Some(mode) => mesh.recolor(mode),
+    }
+    mesh
+}
+
+impl ChunkedTerrain {
+    pub fn new(source: HeightSource, params: ChunkedTerrainParams) -> ChunkedTerrain {
+        let max_lod = params.lod_distances.len();
+        assert!(params.chunk_cells % (1 << max_lod) == 0,
+            "chunk_cells must be divisible by 2^{} to support {} levels of detail", max_lod, max_lod + 1);
+
+        let (request_tx, request_rx) = mpsc::channel::<(ChunkKey, Option<Arc<ColorMode>>)>();
+        let (result_tx, result_rx) = mpsc::channel();
+
+        // The worker lives until the terrain, and with it the request sender, is dropped
+        let worker_source = source.clone();
+        let worker_params = params.clone();
+        thread::spawn(move || {
+            for (key, color_mode) in request_rx {
+                let mesh = build_chunk(&worker_source, &worker_params, key, color_mode.as_deref());
+                if result_tx.send((key, mesh)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        ChunkedTerrain {
+            root     : SceneNode::new(),
+            material : Material::default(),
+            source,
+            params,
+            color_mode : None,
+            coloring   : 0,
+            chunks     : HashMap::new(),
+            pending    : HashSet::new(),
+            requests   : request_tx,
+            results    : result_rx,
+        }
+    }
+
+    // Whether chunks can be colored with `mode`. Curvature and scalar fields are scaled to the mesh they color, so
+    // every chunk would come out on a scale of its own.
+    pub fn supports(mode: &ColorMode) -> bool {
+        !matches!(mode, ColorMode::Curvature(_) | ColorMode::ScalarField(..))
+    }
+
+    // Color the chunks with `mode` from now on. The chunks already loaded are rebuilt, and replaced once they're ready.
+    pub fn set_color_mode(&mut self, mode: &ColorMode) -> Result<(), String> {
+        if !ChunkedTerrain::supports(mode) {
+            return Err("Streamed terrain can't be colored by curvature or a scalar field".to_string());
+        }
+        self.color_mode = Some(Arc::new(mode.clone()));
+        self.coloring += 1;
+        Ok(())
+    }
+
+    fn chunk_size(&self) -> f32 {
+        self.params.chunk_cells as f32 * self.params.terrain.cell_size
+    }
+
+    fn lod_of(&self, x: i32, z: i32, camera: &glm::Vec2) -> u32 {
+        let size = self.chunk_size();
+        let center = glm::vec2((x as f32 + 0.5) * size, (z as f32 + 0.5) * size);
+        let distance = glm::distance(&center, camera);
+        self.params.lod_distances.iter()
+            .position(|&d| distance < d)
+            .unwrap_or(self.params.lod_distances.len()) as u32
+    }
+
+    // Which chunks should be visible from the camera, and at what LOD, nearest first
+    fn wanted_chunks(&self, camera: &glm::Vec2) -> Vec<ChunkKey> {
+        let size = self.chunk_size();
+        let radius = (self.params.view_distance / size).ceil() as i32;
+        let (camera_x, camera_z) = ((camera.x / size).floor() as i32, (camera.y / size).floor() as i32);
+
+        let (min_x, max_x, min_z, max_z) = match self.source.extent() {
+            Some((w, d)) => (
+                0, ((w + self.params.chunk_cells - 1) / self.params.chunk_cells) as i32 - 1,
+                0, ((d + self.params.chunk_cells - 1) / self.params.chunk_cells) as i32 - 1,
+            ),
+            None => (i32::MIN, i32::MAX, i32::MIN, i32::MAX),
+        };
+
+        let mut wanted = vec![];
+        for z in (camera_z - radius).max(min_z)..=(camera_z + radius).min(max_z) {
+            for x in (camera_x - radius).max(min_x)..=(camera_x + radius).min(max_x) {
+                let center = glm::vec2((x as f32 + 0.5) * size, (z as f32 + 0.5) * size);
+                let distance = glm::distance(&center, camera);
+                if distance > self.params.view_distance {
+                    continue;
+                }
+                let lod = self.lod_of(x, z, camera);
+                let edges = [
+                    lod.max(self.lod_of(x, z - 1, camera)),
+                    lod.max(self.lod_of(x + 1, z, camera)),
+                    lod.max(self.lod_of(x, z + 1, camera)),
+                    lod.max(self.lod_of(x - 1, z, camera)),
+                ];
+                wanted.push((distance, ChunkKey { x, z, lod, edges, coloring: self.coloring }));
+            }
+        }
+        wanted.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        wanted.into_iter().map(|(_, key)| key).collect()
+    }
+
+    // Request, upload and unload chunks based on where the camera is. Call this once per frame
+    // from the render thread. `camera_position` is in the local space of `root`.
+    pub unsafe fn update(&mut self, camera_position: &glm::Vec3) {
+        let camera = glm::vec2(camera_position.x, camera_position.z);
+        let wanted = self.wanted_chunks(&camera);
+        let wanted_set: HashSet<ChunkKey> = wanted.iter().cloned().collect();
+
+        // Upload the chunks that have finished building, unless the camera has moved on since they were requested
+        while let Ok((key, mesh)) = self.results.try_recv() {
+            self.pending.remove(&key);
+            if !wanted_set.contains(&key) {
+                continue;
+            }
+            let vao = Vao::from_mesh(&mesh, &VertexLayout::compact());
+            let mut node = SceneNode::from_mesh_vao(&vao);
+            node.material = self.material.clone();
+            let size = self.chunk_size();
+            node.position = glm::vec3((key.x as f32 + 0.5) * size, 0.0, (key.z as f32 + 0.5) * size);
+            self.root.add_child(&node);
+
+            // Replacing a chunk at another LOD or in other colors is done only once the new one is ready, so no holes appear
+            if let Some(old) = self.chunks.insert((key.x, key.z), LoadedChunk { key, _vao: vao, node }) {
+                self.unload(old);
+            }
+        }
+
+        // Unload chunks that have gone out of range
+        let wanted_positions: HashSet<(i32, i32)> = wanted.iter().map(|key| (key.x, key.z)).collect();
+        let out_of_range: Vec<(i32, i32)> = self.chunks.keys()
+            .filter(|position| !wanted_positions.contains(position))
+            .cloned()
+            .collect();
+        for position in out_of_range {
+            if let Some(chunk) = self.chunks.remove(&position) {
+                self.unload(chunk);
+            }
+        }
+
+        // Queue up missing chunks and chunks whose LOD has changed
+        for key in wanted {
+            if self.pending.len() >= self.params.max_pending {
+                break;
+            }
+            let up_to_date = self.chunks.get(&(key.x, key.z)).is_some_and(|chunk| chunk.key == key);
+            if !up_to_date && !self.pending.contains(&key) && self.requests.send((key, self.color_mode.clone())).is_ok() {
+                self.pending.insert(key);
+            }
+        }
+    }
+
+    // Height of the terrain at (x, z) in the local space of `root`, whether or not that chunk is loaded
+    pub fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        let cell_size = self.params.terrain.cell_size;
+        let (grid_x, grid_z) = (x / cell_size, z / cell_size);
+        if let Some((w, d)) = self.source.extent() {
+            if grid_x < 0.0 || grid_z < 0.0 || grid_x > w as f32 || grid_z > d as f32 {
+                return None;
+            }
+        }
+        Some(self.source.sample(grid_x, grid_z) * self.params.terrain.height_scale)
+    }
+
+    // Like `update`, but waits until every chunk in view is loaded, for when there is only one frame
+    pub unsafe fn update_blocking(&mut self, camera_position: &glm::Vec3) {
+        loop {
+            self.update(camera_position);
+            if self.pending.is_empty() {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(1));
+        }
+    }
+
+    // Dropping the chunk's VAO deletes it along with its buffers
+    fn unload(&mut self, chunk: LoadedChunk) {
+        self.root.remove_child(&chunk.node);
+        std::mem::ManuallyDrop::into_inner(chunk.node);
+    }
+
+    #[allow(dead_code)]
+    pub fn loaded_chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params() -> ChunkedTerrainParams {
+        ChunkedTerrainParams {
+            chunk_cells   : 8,
+            lod_distances : vec![20.0, 40.0],
+            view_distance : 100.0,
+            max_pending   : 8,
+            terrain       : TerrainParams::default(),
+        }
+    }
+
+    fn key(x: i32, lod: u32, edges: [u32; 4]) -> ChunkKey {
+        ChunkKey { x, z: 0, lod, edges, coloring: 0 }
+    }
+
+    // The points along the x = `edge_x` side of a chunk, in world space, from north to south
+    fn edge(mesh: &Mesh, key: ChunkKey, edge_x: f32) -> Vec<glm::Vec3> {
+        let size = params().chunk_cells as f32;
+        let offset = glm::vec3((key.x as f32 + 0.5) * size, 0.0, (key.z as f32 + 0.5) * size);
+        let mut points: Vec<glm::Vec3> = mesh.vertices.chunks(3)
+            .map(|v| glm::vec3(v[0], v[1], v[2]) + offset)
+            .filter(|v| (v.x - edge_x).abs() < 1e-4)
+            .collect();
+        points.sort_by(|a, b| a.z.partial_cmp(&b.z).unwrap());
+        points
+    }
+
+    #[test]
+    fn picks_coarser_lods_further_away() {
+        let terrain = ChunkedTerrain::new(HeightSource::Noise(Default::default()), params());
+        let wanted = terrain.wanted_chunks(&glm::vec2(4.0, 4.0));
+        let find = |x: i32, z: i32| wanted.iter().find(|key| (key.x, key.z) == (x, z)).copied();
+
+        assert!(wanted[0].x == 0 && wanted[0].z == 0, "The chunk under the camera comes first");
+        assert_eq!(find(0, 0).unwrap().lod, 0);
+        assert_eq!(find(3, 0).unwrap().lod, 1); // 24 units away
+        assert_eq!(find(6, 0).unwrap().lod, 2); // 48 units away, past the last distance
+        assert!(find(13, 0).is_none(), "Chunks past the view distance aren't loaded");
+
+        // Both sides of every edge agree on the LOD it is stitched to
+        for a in &wanted {
+            if let Some(east) = find(a.x + 1, a.z) {
+                assert_eq!(a.edges[1], east.edges[3]);
+                assert_eq!(a.edges[1], a.lod.max(east.lod));
+            }
+            if let Some(south) = find(a.x, a.z + 1) {
+                assert_eq!(a.edges[2], south.edges[0]);
+            }
+        }
+    }
+
+    #[test]
+    fn stitches_edges_to_coarser_neighbours() {
+        let source = HeightSource::Noise(Default::default());
+        let fine = key(0, 0, [0, 1, 0, 0]);
+        let coarse = key(1, 1, [1, 1, 1, 1]);
+        let fine_edge = edge(&build_chunk(&source, &params(), fine, None), fine, 8.0);
+        let coarse_edge = edge(&build_chunk(&source, &params(), coarse, None), coarse, 8.0);
+        assert_eq!((fine_edge.len(), coarse_edge.len()), (9, 5));
+
+        // The fine chunk has every point of the coarse one, and the points in between lie on the coarse edge
+        for (i, point) in fine_edge.iter().enumerate() {
+            let expected = if i % 2 == 0 {
+                coarse_edge[i / 2]
+            } else {
+                (coarse_edge[i / 2] + coarse_edge[i / 2 + 1]) / 2.0
+            };
+            assert!(glm::distance(point, &expected) < 1e-4, "{} is at {:?}, not {:?}", i, point, expected);
+        }
+
+        // Without stitching the points in between follow the terrain, off the coarse edge
+        let unstitched = key(0, 0, [0, 0, 0, 0]);
+        let unstitched_edge = edge(&build_chunk(&source, &params(), unstitched, None), unstitched, 8.0);
+        assert!((1..9).step_by(2).any(|i| {
+            (unstitched_edge[i].y - (coarse_edge[i / 2].y + coarse_edge[i / 2 + 1].y) / 2.0).abs() > 1e-4
+        }));
+    }
+}
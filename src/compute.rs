@@ -0,0 +1,211 @@
+use std::{marker::PhantomData, mem, ops::BitOr, ptr};
+
+use crate::gl_object::Buffer;
+use crate::shader::{Shader, ShaderBuilder, ShaderError};
+
+// How many work groups of `local_size` it takes to cover `size` invocations
+fn work_groups(size: u32, local_size: u32) -> u32 {
+    let local_size = local_size.max(1);
+    (size + local_size - 1) / local_size
+}
+
+// A linked program with a single compute stage
+pub struct ComputeShader {
+    pub shader     : Shader,
+    pub local_size : [u32; 3], // The local_size_x/y/z declared in the shader
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Copy)]
+pub enum ImageAccess {
+    ReadOnly,
+    WriteOnly,
+    ReadWrite,
+}
+
+// Which kinds of reads have to see the writes made by earlier dispatches, combine them with `|`
+#[derive(Clone, Copy)]
+pub struct Barrier(gl::types::GLbitfield);
+
+#[allow(dead_code)]
+impl Barrier {
+    pub const STORAGE_BUFFER : Barrier = Barrier(gl::SHADER_STORAGE_BARRIER_BIT);
+    pub const IMAGE_ACCESS   : Barrier = Barrier(gl::SHADER_IMAGE_ACCESS_BARRIER_BIT);
+    pub const TEXTURE_FETCH  : Barrier = Barrier(gl::TEXTURE_FETCH_BARRIER_BIT);
+    pub const VERTEX_ATTRIB  : Barrier = Barrier(gl::VERTEX_ATTRIB_ARRAY_BARRIER_BIT);
+    pub const ELEMENT_ARRAY  : Barrier = Barrier(gl::ELEMENT_ARRAY_BARRIER_BIT);
+    pub const UNIFORM        : Barrier = Barrier(gl::UNIFORM_BARRIER_BIT);
+    pub const COMMAND        : Barrier = Barrier(gl::COMMAND_BARRIER_BIT);
+    pub const BUFFER_UPDATE  : Barrier = Barrier(gl::BUFFER_UPDATE_BARRIER_BIT);
+    pub const FRAMEBUFFER    : Barrier = Barrier(gl::FRAMEBUFFER_BARRIER_BIT);
+    pub const ALL            : Barrier = Barrier(gl::ALL_BARRIER_BITS);
+}
+
+impl BitOr for Barrier {
+    type Output = Barrier;
+    fn bitor(self, other: Barrier) -> Barrier {
+        Barrier(self.0 | other.0)
+    }
+}
+
+// Wait for earlier shader writes before the given kinds of reads
+#[allow(dead_code)]
+pub unsafe fn memory_barrier(barrier: Barrier) {
+    gl::MemoryBarrier(barrier.0);
+}
+
+/// Types that every bit pattern is a valid value of, so the GPU can write them.
+///
+/// # Safety
+/// Only implement this for types made of numbers, without padding.
+pub unsafe trait PlainData: Copy {}
+
+unsafe impl PlainData for f32 {}
+unsafe impl PlainData for i32 {}
+unsafe impl PlainData for u32 {}
+unsafe impl<T: PlainData, const N: usize> PlainData for [T; N] {}
+
+// A shader storage buffer holding an array of T. T has to match the std430 layout of the buffer in the shader.
+pub struct StorageBuffer<T: PlainData> {
+    pub buffer    : Buffer,
+    pub len       : usize,
+    element       : PhantomData<T>,
+}
+
+#[allow(dead_code)]
+impl<T: PlainData> StorageBuffer<T> {
+    pub unsafe fn new(data: &[T]) -> StorageBuffer<T> {
+        let buffer = Buffer::new();
+        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, buffer.id());
+        gl::BufferData(gl::SHADER_STORAGE_BUFFER, mem::size_of_val(data) as isize,
+            if data.is_empty() { ptr::null() } else { data.as_ptr() as *const _ }, gl::DYNAMIC_COPY);
+        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
+        StorageBuffer { buffer, len: data.len(), element: PhantomData }
+    }
+
+    // Overwrite the buffer, starting at element `offset`
+    pub unsafe fn write(&self, offset: usize, data: &[T]) {
+        assert!(offset + data.len() <= self.len, "Write past the end of the storage buffer");
+        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, self.buffer.id());
+        gl::BufferSubData(gl::SHADER_STORAGE_BUFFER, (offset * mem::size_of::<T>()) as isize,
+            mem::size_of_val(data) as isize, data.as_ptr() as *const _);
+        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
+    }
+
+    // Copy the buffer back to the CPU. Put up a `Barrier::BUFFER_UPDATE` after the dispatch writing it first.
+    pub unsafe fn read(&self) -> Vec<T> {
+        let mut data = Vec::<T>::with_capacity(self.len);
+        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, self.buffer.id());
+        gl::GetBufferSubData(gl::SHADER_STORAGE_BUFFER, 0, (self.len * mem::size_of::<T>()) as isize,
+            data.as_mut_ptr() as *mut _);
+        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
+        data.set_len(self.len);
+        data
+    }
+}
+
+#[allow(dead_code)]
+impl ComputeShader {
+    // Build a compute shader from a .comp file
+    pub unsafe fn new(shader_path: &str) -> Result<ComputeShader, ShaderError> {
+        let shader = ShaderBuilder::new()
+            .attach_file(shader_path)
+            .and_then(|builder| builder.link())?;
+        Ok(ComputeShader::from_shader(shader))
+    }
+
+    pub unsafe fn from_shader(shader: Shader) -> ComputeShader {
+        let mut local_size = [0i32; 3];
+        gl::GetProgramiv(shader.program_id(), gl::COMPUTE_WORK_GROUP_SIZE, local_size.as_mut_ptr());
+        ComputeShader {
+            shader,
+            local_size: [local_size[0] as u32, local_size[1] as u32, local_size[2] as u32],
+        }
+    }
+
+    // Run x * y * z work groups. Panics if that's more than the driver allows in any dimension.
+    pub unsafe fn dispatch(&self, x: u32, y: u32, z: u32) {
+        for (axis, &count) in [x, y, z].iter().enumerate() {
+            let mut max_count = 0;
+            gl::GetIntegeri_v(gl::MAX_COMPUTE_WORK_GROUP_COUNT, axis as u32, &mut max_count);
+            assert!(count <= max_count as u32, "Work group count {} exceeds the limit of {} in dimension {}", count, max_count, axis);
+        }
+        self.shader.activate();
+        gl::DispatchCompute(x, y, z);
+    }
+
+    // Run enough work groups to cover `size` invocations in each dimension, rounding up.
+    // The shader has to skip invocations past the end itself.
+    pub unsafe fn dispatch_size(&self, size: [u32; 3]) {
+        self.dispatch(
+            work_groups(size[0], self.local_size[0]),
+            work_groups(size[1], self.local_size[1]),
+            work_groups(size[2], self.local_size[2]),
+        );
+    }
+
+    // Bind a buffer to `layout(std430, binding = N) buffer`
+    pub unsafe fn bind_storage_buffer<T: PlainData>(&self, binding: u32, buffer: &StorageBuffer<T>) {
+        gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, binding, buffer.buffer.id());
+    }
+
+    // Bind a texture level to `layout(<format>, binding = N) uniform image2D`, `format` being e.g. gl::RGBA32F
+    pub unsafe fn bind_image(&self, unit: u32, texture_id: u32, level: i32, access: ImageAccess, format: gl::types::GLenum) {
+        let access = match access {
+            ImageAccess::ReadOnly  => gl::READ_ONLY,
+            ImageAccess::WriteOnly => gl::WRITE_ONLY,
+            ImageAccess::ReadWrite => gl::READ_WRITE,
+        };
+        // Layered binding, so 3D textures and arrays are bound as a whole
+        gl::BindImageTexture(unit, texture_id, level, gl::TRUE, 0, access, format);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::{self, HeadlessContext};
+    use crate::shader::ShaderType;
+
+    // Squares every value, with a local size that doesn't divide the number of values
+    const SQUARE: &str = "#version 430 core
+layout(local_size_x = 4) in;
+layout(std430, binding = 0) buffer Values { float values[]; };
+void main() {
+    uint i = gl_GlobalInvocationID.x;
+    if (i < uint(values.length())) {
+        values[i] = values[i] * values[i];
+    }
+}
+";
+
+    #[test]
+    fn rounds_work_groups_up() {
+        assert_eq!(work_groups(10, 4), 3);
+        assert_eq!(work_groups(8, 4), 2);
+        assert_eq!(work_groups(0, 4), 0);
+        assert_eq!(work_groups(1, 0), 1);
+    }
+
+    #[test]
+    fn dispatch_writes_storage_buffer() {
+        let _lock = headless::TEST_GL_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let _context = HeadlessContext::new().unwrap();
+        unsafe {
+            let shader = ShaderBuilder::new()
+                .compile_shader(SQUARE, ShaderType::Compute)
+                .and_then(|builder| builder.link())
+                .unwrap();
+            let compute = ComputeShader::from_shader(shader);
+            assert_eq!(compute.local_size, [4, 1, 1]);
+
+            let values: Vec<f32> = (0..10).map(|i| i as f32).collect();
+            let buffer = StorageBuffer::new(&values);
+            buffer.write(9, &[-3.0]);
+            compute.bind_storage_buffer(0, &buffer);
+            compute.dispatch_size([10, 1, 1]);
+            memory_barrier(Barrier::BUFFER_UPDATE);
+            assert_eq!(buffer.read(), vec![0.0, 1.0, 4.0, 9.0, 16.0, 25.0, 36.0, 49.0, 64.0, 9.0]);
+        }
+    }
+}
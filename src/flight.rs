@@ -0,0 +1,109 @@
+extern crate nalgebra_glm as glm;
+
+use crate::scene_graph::SceneNode;
+
+// The pilot's controls, each from -1 to 1
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct FlightControls {
+    pub collective : f32,       // Climb or descend, 0 hovers
+    pub cyclic     : glm::Vec2, // Tilt towards x to the right and y forward, which the helicopter then flies towards
+    pub pedals     : f32,       // Turn right or left
+}
+
+// A helicopter flown by hand. Not a real flight model: it hovers with the controls centered, and tilting
+// it makes it accelerate the way it leans.
+pub struct Flight {
+    pub position     : glm::Vec3,
+    pub velocity     : glm::Vec3,
+    pub yaw          : f32, // Radians, as for the scene nodes. The nose points down the negative z-axis at 0.
+    pub pitch        : f32, // Radians, nose up is positive
+    pub roll         : f32, // Radians, rolling left is positive
+    pub climb_rate   : f32, // Units per second at full collective
+    pub max_tilt     : f32, // Radians tilted at full cyclic
+    pub tilt_rate    : f32, // How quickly the tilt follows the cyclic, per second
+    pub acceleration : f32, // Units per second squared per radian of tilt
+    pub drag         : f32, // How much of the horizontal velocity is lost per second
+    pub turn_rate    : f32, // Radians per second at full pedals
+    pub clearance    : f32, // How far above the terrain the helicopter is kept
+}
+
+impl Default for Flight {
+    fn default() -> Self {
+        Flight {
+            position     : glm::zero(),
+            velocity     : glm::zero(),
+            yaw          : 0.0,
+            pitch        : 0.0,
+            roll         : 0.0,
+            climb_rate   : 10.0,
+            max_tilt     : 0.35,
+            tilt_rate    : 4.0,
+            acceleration : 60.0,
+            drag         : 0.8,
+            turn_rate    : 1.5,
+            clearance    : 1.0,
+        }
+    }
+}
+
+impl FlightControls {
+    // Every control limited to [-1, 1], as keys and sticks bound to the same control add up
+    pub fn clamped(&self) -> FlightControls {
+        FlightControls {
+            collective : self.collective.clamp(-1.0, 1.0),
+            cyclic     : glm::vec2(self.cyclic.x.clamp(-1.0, 1.0), self.cyclic.y.clamp(-1.0, 1.0)),
+            pedals     : self.pedals.clamp(-1.0, 1.0),
+        }
+    }
+}
+
+impl Flight {
+    // Take over from wherever the node is, hovering
+    pub fn from_node(node: &SceneNode) -> Flight {
+        Flight {
+            position : node.position,
+            yaw      : node.rotation.y,
+            ..Default::default()
+        }
+    }
+
+    // The nose's direction along the ground
+    pub fn forward(&self) -> glm::Vec3 {
+        glm::vec3(-self.yaw.sin(), 0.0, -self.yaw.cos())
+    }
+
+    pub fn right(&self) -> glm::Vec3 {
+        glm::vec3(self.yaw.cos(), 0.0, -self.yaw.sin())
+    }
+
+    // Fly `delta_time` seconds with the given controls. `ground` is the height of the terrain below, if any.
+    pub fn update(&mut self, controls: &FlightControls, ground: Option<f32>, delta_time: f32) {
+        let controls = controls.clamped();
+        self.yaw -= controls.pedals * self.turn_rate * delta_time;
+
+        // Pushing the cyclic forward dips the nose, and to the right lowers the right side
+        let blend = 1.0 - (-self.tilt_rate * delta_time).exp();
+        self.pitch += (-controls.cyclic.y * self.max_tilt - self.pitch) * blend;
+        self.roll += (-controls.cyclic.x * self.max_tilt - self.roll) * blend;
+
+        let thrust = self.forward() * -self.pitch + self.right() * -self.roll;
+        let horizontal = glm::vec3(self.velocity.x, 0.0, self.velocity.z);
+        let horizontal = horizontal + thrust * self.acceleration * delta_time;
+        let horizontal = horizontal * (-self.drag * delta_time).exp();
+        self.velocity = glm::vec3(horizontal.x, controls.collective * self.climb_rate, horizontal.z);
+        self.position += self.velocity * delta_time;
+
+        if let Some(ground) = ground {
+            if self.position.y < ground + self.clearance {
+                self.position.y = ground + self.clearance;
+                self.velocity.y = 0.0;
+            }
+        }
+    }
+
+    // Put the node where the helicopter is
+    pub fn apply(&self, node: &mut SceneNode) {
+        node.position = self.position;
+        node.rotation = glm::vec3(self.pitch, self.yaw, self.roll);
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc;
+
+use crate::input::{Axis, Button, GamepadAxis, GamepadButton, InputEvent};
+
+// A gamepad driven by code instead of a player, for tests. Its events go to the same place as a real one's.
+#[allow(dead_code)]
+pub struct VirtualGamepad {
+    events: mpsc::Sender<InputEvent>,
+}
+
+#[allow(dead_code)]
+impl VirtualGamepad {
+    pub fn new(events: mpsc::Sender<InputEvent>) -> VirtualGamepad {
+        VirtualGamepad { events }
+    }
+
+    pub fn press(&self, button: GamepadButton) {
+        let _ = self.events.send(InputEvent::Pressed(Button::Gamepad(button)));
+    }
+
+    pub fn release(&self, button: GamepadButton) {
+        let _ = self.events.send(InputEvent::Released(Button::Gamepad(button)));
+    }
+
+    pub fn set_axis(&self, axis: GamepadAxis, position: f32) {
+        let _ = self.events.send(InputEvent::AxisChanged(Axis::Gamepad(axis), position));
+    }
+
+    // Let go of everything, as when a gamepad is unplugged
+    pub fn disconnect(&self) {
+        disconnect(&self.events);
+    }
+}
+
+fn disconnect(events: &mpsc::Sender<InputEvent>) {
+    for &button in GamepadButton::ALL {
+        let _ = events.send(InputEvent::Released(Button::Gamepad(button)));
+    }
+    for &axis in GamepadAxis::ALL {
+        let _ = events.send(InputEvent::AxisChanged(Axis::Gamepad(axis), 0.0));
+    }
+}
+
+// What each connected gamepad holds, keyed by gamepad id. All gamepads act as one, so a button is down while
+// any gamepad holds it, and unplugging a gamepad lets go of only what that one held.
+#[allow(dead_code)]
+#[derive(Default)]
+pub struct Gamepads {
+    buttons : HashMap<usize, HashSet<GamepadButton>>,
+    axes    : HashMap<usize, HashMap<GamepadAxis, f32>>,
+}
+
+#[allow(dead_code)]
+impl Gamepads {
+    fn held_by_another(&self, pad: usize, button: GamepadButton) -> bool {
+        self.buttons.iter().any(|(&other, held)| other != pad && held.contains(&button))
+    }
+
+    // Note an event from a gamepad, and return what it means for the gamepads as a whole, if anything
+    pub fn update(&mut self, pad: usize, event: InputEvent) -> Option<InputEvent> {
+        match event {
+            InputEvent::Pressed(Button::Gamepad(button)) => {
+                let held = self.held_by_another(pad, button);
+                self.buttons.entry(pad).or_default().insert(button);
+                Some(event).filter(|_| !held)
+            },
+            InputEvent::Released(Button::Gamepad(button)) => {
+                self.buttons.entry(pad).or_default().remove(&button);
+                Some(event).filter(|_| !self.held_by_another(pad, button))
+            },
+            InputEvent::AxisChanged(Axis::Gamepad(axis), position) => {
+                self.axes.entry(pad).or_default().insert(axis, position);
+                Some(event)
+            },
+            _ => Some(event),
+        }
+    }
+
+    // Forget a gamepad that was unplugged. Returns the events that let go of what it held, with axes that
+    // another gamepad holds away from the center going back to where that one holds them.
+    pub fn disconnect(&mut self, pad: usize) -> Vec<InputEvent> {
+        let buttons = self.buttons.remove(&pad).unwrap_or_default();
+        let axes = self.axes.remove(&pad).unwrap_or_default();
+        let released = GamepadButton::ALL.iter()
+            .filter(|&button| buttons.contains(button) && !self.held_by_another(pad, *button))
+            .map(|&button| InputEvent::Released(Button::Gamepad(button)));
+        let centered = GamepadAxis::ALL.iter()
+            .filter(|&axis| axes.get(axis).is_some_and(|&position| position != 0.0))
+            .map(|&axis| {
+                let position = self.axes.values().filter_map(|axes| axes.get(&axis)).find(|&&position| position != 0.0);
+                InputEvent::AxisChanged(Axis::Gamepad(axis), position.copied().unwrap_or(0.0))
+            });
+        released.chain(centered).collect()
+    }
+}
+
+#[cfg(feature = "gamepad")]
+fn button(button: gilrs::Button) -> Option<GamepadButton> {
+    let button = match button {
+        gilrs::Button::South => GamepadButton::South,
+        gilrs::Button::East => GamepadButton::East,
+        gilrs::Button::North => GamepadButton::North,
+        gilrs::Button::West => GamepadButton::West,
+        gilrs::Button::LeftTrigger => GamepadButton::LeftBumper,
+        gilrs::Button::RightTrigger => GamepadButton::RightBumper,
+        gilrs::Button::LeftThumb => GamepadButton::LeftStick,
+        gilrs::Button::RightThumb => GamepadButton::RightStick,
+        gilrs::Button::Select => GamepadButton::Select,
+        gilrs::Button::Start => GamepadButton::Start,
+        gilrs::Button::DPadUp => GamepadButton::Up,
+        gilrs::Button::DPadDown => GamepadButton::Down,
+        gilrs::Button::DPadLeft => GamepadButton::Left,
+        gilrs::Button::DPadRight => GamepadButton::Right,
+        _ => return None,
+    };
+    Some(button)
+}
+
+#[cfg(feature = "gamepad")]
+fn axis(axis: gilrs::Axis) -> Option<GamepadAxis> {
+    let axis = match axis {
+        gilrs::Axis::LeftStickX => GamepadAxis::LeftX,
+        gilrs::Axis::LeftStickY => GamepadAxis::LeftY,
+        gilrs::Axis::RightStickX => GamepadAxis::RightX,
+        gilrs::Axis::RightStickY => GamepadAxis::RightY,
+        _ => return None,
+    };
+    Some(axis)
+}
+
+// What a gilrs event means for the input, if anything
+#[cfg(feature = "gamepad")]
+fn translate(event: gilrs::EventType) -> Option<InputEvent> {
+    let event = match event {
+        gilrs::EventType::ButtonPressed(b, _) => InputEvent::Pressed(Button::Gamepad(button(b)?)),
+        gilrs::EventType::ButtonReleased(b, _) => InputEvent::Released(Button::Gamepad(button(b)?)),
+        gilrs::EventType::AxisChanged(a, position, _) => InputEvent::AxisChanged(Axis::Gamepad(axis(a)?), position),
+        // The triggers are analog, and come as how far the trigger button is pulled
+        gilrs::EventType::ButtonChanged(gilrs::Button::LeftTrigger2, position, _) =>
+            InputEvent::AxisChanged(Axis::Gamepad(GamepadAxis::LeftTrigger), position),
+        gilrs::EventType::ButtonChanged(gilrs::Button::RightTrigger2, position, _) =>
+            InputEvent::AxisChanged(Axis::Gamepad(GamepadAxis::RightTrigger), position),
+        _ => return None,
+    };
+    Some(event)
+}
+
+// Send the events of every connected gamepad to `events` from a thread of their own, until the receiver is gone.
+// All gamepads act as one, see Gamepads.
+#[cfg(feature = "gamepad")]
+pub fn spawn(events: mpsc::Sender<InputEvent>) {
+    std::thread::spawn(move || {
+        let mut gilrs = match gilrs::Gilrs::new() {
+            Ok(gilrs) => gilrs,
+            Err(e) => {
+                println!("No gamepad support: {}", e);
+                return;
+            },
+        };
+        for (_, gamepad) in gilrs.gamepads() {
+            println!("Gamepad: {}", gamepad.name());
+        }
+        let mut gamepads = Gamepads::default();
+        while let Some(event) = gilrs.next_event_blocking(None) {
+            let pad = usize::from(event.id);
+            let input_events = match event.event {
+                gilrs::EventType::Connected => {
+                    println!("Gamepad connected: {}", gilrs.gamepad(event.id).name());
+                    vec![]
+                },
+                gilrs::EventType::Disconnected => gamepads.disconnect(pad),
+                event => translate(event).and_then(|event| gamepads.update(pad, event)).into_iter().collect(),
+            };
+            for event in input_events {
+                if events.send(event).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+}
+
+// Built without gamepad support, so there are no gamepads to listen to
+#[cfg(not(feature = "gamepad"))]
+pub fn spawn(_events: mpsc::Sender<InputEvent>) { }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOUTH: InputEvent = InputEvent::Pressed(Button::Gamepad(GamepadButton::South));
+    const SOUTH_UP: InputEvent = InputEvent::Released(Button::Gamepad(GamepadButton::South));
+
+    fn left_x(position: f32) -> InputEvent {
+        InputEvent::AxisChanged(Axis::Gamepad(GamepadAxis::LeftX), position)
+    }
+
+    #[test]
+    fn buttons_are_down_while_any_gamepad_holds_them() {
+        let mut gamepads = Gamepads::default();
+        assert_eq!(gamepads.update(0, SOUTH), Some(SOUTH));
+        assert_eq!(gamepads.update(1, SOUTH), None);
+        assert_eq!(gamepads.update(0, SOUTH_UP), None);
+        assert_eq!(gamepads.update(1, SOUTH_UP), Some(SOUTH_UP));
+    }
+
+    #[test]
+    fn disconnecting_lets_go_of_only_what_that_gamepad_held() {
+        let mut gamepads = Gamepads::default();
+        gamepads.update(0, SOUTH);
+        gamepads.update(0, InputEvent::Pressed(Button::Gamepad(GamepadButton::Start)));
+        gamepads.update(0, left_x(0.5));
+        gamepads.update(1, InputEvent::Pressed(Button::Gamepad(GamepadButton::Start)));
+        gamepads.update(1, InputEvent::AxisChanged(Axis::Gamepad(GamepadAxis::RightY), -1.0));
+
+        assert_eq!(gamepads.disconnect(0), vec![SOUTH_UP, left_x(0.0)]);
+        assert_eq!(gamepads.disconnect(0), vec![]);
+        assert_eq!(gamepads.disconnect(1), vec![
+            InputEvent::Released(Button::Gamepad(GamepadButton::Start)),
+            InputEvent::AxisChanged(Axis::Gamepad(GamepadAxis::RightY), 0.0),
+        ]);
+    }
+
+    #[test]
+    fn disconnecting_hands_axes_back_to_the_other_gamepads() {
+        let mut gamepads = Gamepads::default();
+        gamepads.update(0, left_x(-0.25));
+        assert_eq!(gamepads.update(1, left_x(0.75)), Some(left_x(0.75)));
+        assert_eq!(gamepads.disconnect(1), vec![left_x(-0.25)]);
+    }
+}
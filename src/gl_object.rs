@@ -0,0 +1,84 @@
+use std::marker::PhantomData;
+
+// Owning wrappers around GL object names, which delete the object when dropped.
+// GL objects belong to the context that made them, so none of these are Send or Sync. They can
+// only be dropped on the thread they were made on, which has to be the one owning the context.
+type ThreadBound = PhantomData<*const ()>;
+
+// Objects made and deleted with glGen*/glDelete*
+macro_rules! gl_object {
+    ($(#[$attr:meta])* $name:ident, $gen:ident, $delete:ident) => {
+        $(#[$attr])*
+        pub struct $name {
+            id      : u32,
+            _thread : ThreadBound,
+        }
+
+        $(#[$attr])*
+        impl $name {
+            pub unsafe fn new() -> $name {
+                let mut id = 0;
+                gl::$gen(1, &mut id);
+                $name { id, _thread: PhantomData }
+            }
+
+            pub fn id(&self) -> u32 {
+                self.id
+            }
+        }
+
+        impl Drop for $name {
+            fn drop(&mut self) {
+                unsafe { gl::$delete(1, &self.id) };
+            }
+        }
+    };
+}
+
+gl_object!(VertexArray, GenVertexArrays, DeleteVertexArrays);
+gl_object!(Buffer, GenBuffers, DeleteBuffers);
+gl_object!(#[allow(dead_code)] Texture, GenTextures, DeleteTextures);
+gl_object!(Framebuffer, GenFramebuffers, DeleteFramebuffers);
+gl_object!(Renderbuffer, GenRenderbuffers, DeleteRenderbuffers);
+
+pub struct Program {
+    id      : u32,
+    _thread : ThreadBound,
+}
+
+pub struct ShaderObject {
+    id      : u32,
+    _thread : ThreadBound,
+}
+
+impl Program {
+    pub unsafe fn new() -> Program {
+        Program { id: gl::CreateProgram(), _thread: PhantomData }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+impl Drop for Program {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteProgram(self.id) };
+    }
+}
+
+impl ShaderObject {
+    pub unsafe fn new(shader_type: gl::types::GLenum) -> ShaderObject {
+        ShaderObject { id: gl::CreateShader(shader_type), _thread: PhantomData }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+impl Drop for ShaderObject {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteShader(self.id) };
+    }
+}
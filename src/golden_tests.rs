@@ -0,0 +1,293 @@
+// Golden image tests: canonical scenes are rendered offscreen and compared to the reference images
+// in tests/golden. Renders that differ are written to target/golden, next to an image marking the
+// pixels that differ.
+//
+//   GOLDEN_UPDATE=1 cargo test -- --include-ignored   rewrites the references, after an intended change to the visuals
+//
+// Every test needs libEGL. The helicopter tests also need the models from handout_code_v2.zip in ./resources,
+// which aren't checked in, so they are ignored unless run with `cargo test -- --ignored`.
+//
+// The references are rendered with Mesa's llvmpipe (LIBGL_ALWAYS_SOFTWARE=1), the helicopter ones from the
+// unmodified handout models. Other drivers rasterize slightly differently, which the tolerance is meant to absorb.
+extern crate nalgebra_glm as glm;
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+use image::{Rgba, RgbaImage};
+
+use crate::headless::{self, HeadlessContext};
+use crate::mesh::{Mesh, Primitive};
+use crate::render_target::RenderTarget;
+use crate::scene::{self, Renderer, Scene, SceneOptions, TerrainSource, View};
+use crate::scene_graph::{SceneNode, Node};
+use crate::shader_library::Material;
+use crate::uniform_buffer::{Light, LightUniforms};
+use crate::util;
+use crate::vao::{Vao, VertexLayout};
+
+const WIDTH: u32 = 320;
+const HEIGHT: u32 = 240;
+
+// Pixels further apart than this are different, as a CIE76 color difference. Around 2.3 is just noticeable.
+const MAX_DELTA_E: f32 = 3.0;
+// The share of pixels allowed to differ before a test fails
+const MAX_DIFFERENT: f32 = 0.002;
+
+fn env_flag(name: &str) -> bool {
+    std::env::var(name).is_ok_and(|value| value == "1")
+}
+
+fn view(camera_position: glm::Vec3, target: glm::Vec3) -> View {
+    View {
+        view: glm::look_at(&camera_position, &target, &glm::vec3(0.0, 1.0, 0.0)),
+        projection: glm::perspective(WIDTH as f32 / HEIGHT as f32, 0.8, 0.5, 1000.0),
+        camera_position,
+        reverse_z: false,
+    }
+}
+
+// Run `draw` with a fresh context and offscreen target bound, and read back what it drew
+fn render<F: FnOnce()>(test: &str, draw: F) -> RgbaImage {
+    let _lock = headless::TEST_GL_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let _context = HeadlessContext::new().unwrap_or_else(|e| panic!("{}: {}", test, e));
+    unsafe {
+        scene::setup_gl_state();
+        gl::Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
+        gl::DebugMessageCallback(Some(util::debug_callback), ptr::null());
+
+        let target = RenderTarget::new(WIDTH, HEIGHT).unwrap();
+        target.bind();
+        draw();
+        target.read_pixels()
+    }
+}
+
+// sRGB to CIELAB, with a D65 white point
+fn lab(pixel: &Rgba<u8>) -> [f32; 3] {
+    let linear = |c: u8| {
+        let c = c as f32 / 255.0;
+        if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+    };
+    let (r, g, b) = (linear(pixel[0]), linear(pixel[1]), linear(pixel[2]));
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+    let f = |t: f32| if t > 0.008856 { t.cbrt() } else { 7.787 * t + 16.0 / 116.0 };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+fn delta_e(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+// Whether the pixel at (x, y) in `a` has a close match in `b` at or next to the same position.
+// Looking at the neighbours forgives edges that moved by a pixel.
+fn has_match(a: &[[f32; 3]], b: &[[f32; 3]], x: u32, y: u32) -> bool {
+    let pixel = &a[(y * WIDTH + x) as usize];
+    (y.saturating_sub(1)..=(y + 1).min(HEIGHT - 1)).any(|ny| {
+        (x.saturating_sub(1)..=(x + 1).min(WIDTH - 1)).any(|nx| delta_e(pixel, &b[(ny * WIDTH + nx) as usize]) <= MAX_DELTA_E)
+    })
+}
+
+// Count the pixels that differ, and mark them in red over a faded copy of the reference
+fn compare(actual: &RgbaImage, expected: &RgbaImage) -> (usize, RgbaImage) {
+    let actual_lab: Vec<[f32; 3]> = actual.pixels().map(lab).collect();
+    let expected_lab: Vec<[f32; 3]> = expected.pixels().map(lab).collect();
+
+    let mut different = 0;
+    let diff = RgbaImage::from_fn(WIDTH, HEIGHT, |x, y| {
+        if has_match(&actual_lab, &expected_lab, x, y) && has_match(&expected_lab, &actual_lab, x, y) {
+            let lightness = expected_lab[(y * WIDTH + x) as usize][0] / 100.0;
+            let faded = (191.0 + 64.0 * lightness) as u8;
+            Rgba([faded, faded, faded, 255])
+        } else {
+            different += 1;
+            Rgba([255, 0, 0, 255])
+        }
+    });
+    (different, diff)
+}
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden")
+}
+
+fn output_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("target").join("golden")
+}
+
+fn check(name: &str, actual: RgbaImage) {
+    let reference = golden_dir().join(format!("{}.png", name));
+    if env_flag("GOLDEN_UPDATE") {
+        std::fs::create_dir_all(golden_dir()).unwrap();
+        actual.save(&reference).unwrap();
+        return;
+    }
+
+    let output = output_dir();
+    std::fs::create_dir_all(&output).unwrap();
+    let actual_path = output.join(format!("{}.actual.png", name));
+    let expected = match image::open(&reference) {
+        Ok(expected) => expected.to_rgba8(),
+        Err(e) => {
+            actual.save(&actual_path).unwrap();
+            panic!("{}: No reference image at {} ({}). Run with GOLDEN_UPDATE=1 to create it, the render is in {}",
+                name, reference.display(), e, actual_path.display());
+        },
+    };
+    if expected.dimensions() != actual.dimensions() {
+        actual.save(&actual_path).unwrap();
+        panic!("{}: Rendered {:?}, but the reference is {:?}", name, actual.dimensions(), expected.dimensions());
+    }
+
+    let (different, diff) = compare(&actual, &expected);
+    let allowed = (MAX_DIFFERENT * (WIDTH * HEIGHT) as f32) as usize;
+    if different > allowed {
+        let diff_path = output.join(format!("{}.diff.png", name));
+        actual.save(&actual_path).unwrap();
+        diff.save(&diff_path).unwrap();
+        panic!("{}: {} pixels differ from {}, at most {} may. See {} and {}",
+            name, different, reference.display(), allowed, actual_path.display(), diff_path.display());
+    }
+}
+
+// A cube and a sphere standing on a plane
+struct Primitives {
+    root  : Node,
+    nodes : Vec<Node>,
+    _vaos : Vec<Vao>,
+}
+
+impl Primitives {
+    unsafe fn new(material: &Material) -> Primitives {
+        let mut root = SceneNode::new();
+        let mut nodes = vec![];
+        let mut vaos = vec![];
+        let shapes: Vec<(Mesh, glm::Vec3, f32)> = vec![
+            (Primitive::plane(10.0, [0.6, 0.6, 0.6, 1.0]), glm::vec3(0.0, 0.0, 0.0), 0.0),
+            (Primitive::cube(2.0, [0.8, 0.2, 0.2, 1.0]), glm::vec3(-1.8, 1.0, 0.0), 0.6),
+            (Primitive::sphere(1.2, 32, 16, [0.2, 0.3, 0.8, 1.0]), glm::vec3(1.8, 1.2, 0.5), 0.0),
+        ];
+        for (mesh, position, rotation) in shapes {
+            let vao = Vao::from_mesh(&mesh, &VertexLayout::standard());
+            let mut node = SceneNode::from_mesh_vao(&vao);
+            node.position = position;
+            node.rotation.y = rotation;
+            node.material = material.clone();
+            root.add_child(&node);
+            nodes.push(node);
+            vaos.push(vao);
+        }
+        Primitives { root, nodes, _vaos: vaos }
+    }
+
+    // Seen from the side the default light comes from
+    fn view() -> View {
+        view(glm::vec3(-5.0, 5.0, -7.0), glm::vec3(0.0, 0.5, 0.0))
+    }
+
+    unsafe fn render(material: &Material, lights: &LightUniforms) {
+        let mut renderer = Renderer::new();
+        renderer.light_uniforms.update(lights);
+        let mut primitives = Primitives::new(material);
+        renderer.render(&mut primitives.root, &Primitives::view(), 0.0);
+    }
+}
+
+impl Drop for Primitives {
+    fn drop(&mut self) {
+        for node in self.nodes.drain(..) {
+            std::mem::ManuallyDrop::into_inner(node);
+        }
+        unsafe { std::mem::ManuallyDrop::drop(&mut self.root) };
+    }
+}
+
+fn helicopter_at(name: &str, time: f32) {
+    assert!(Path::new("./resources/helicopter.obj").exists(), "{}: ./resources/helicopter.obj is missing, unpack it from handout_code_v2.zip", name);
+    let image = render(name, || unsafe {
+        // Without terrain, so only the helicopter's hierarchy is tested
+        let mut scene = Scene::new(&SceneOptions { terrain: TerrainSource::None });
+        scene.animate(time);
+        // From above and on the side the default light comes from, so the rotors are visible
+        let body = scene.body_node.position;
+        let view = view(body + glm::vec3(-10.0, 7.0, -10.0), body);
+        scene.render(&view, time);
+    });
+    check(name, image);
+}
+
+#[test]
+fn primitives() {
+    let image = render("primitives", || unsafe {
+        Primitives::render(&Material::default(), &LightUniforms::default());
+    });
+    check("primitives", image);
+}
+
+#[test]
+fn primitives_unlit() {
+    let image = render("primitives_unlit", || unsafe {
+        Primitives::render(&Material::default().with_flag("LIT", false), &LightUniforms::default());
+    });
+    check("primitives_unlit", image);
+}
+
+#[test]
+fn primitives_normals() {
+    let image = render("primitives_normals", || unsafe {
+        Primitives::render(&Material::default().with_int("DEBUG_VIEW", 1), &LightUniforms::default());
+    });
+    check("primitives_normals", image);
+}
+
+#[test]
+fn lighting_ambient_only() {
+    let image = render("lighting_ambient_only", || unsafe {
+        Primitives::render(&Material::default(), &LightUniforms::new(glm::vec3(0.4, 0.4, 0.4), &[]));
+    });
+    check("lighting_ambient_only", image);
+}
+
+#[test]
+fn lighting_from_above() {
+    let image = render("lighting_from_above", || unsafe {
+        let lights = LightUniforms::new(glm::vec3(0.1, 0.1, 0.1), &[
+            Light::directional(glm::vec3(0.0, -1.0, 0.0), glm::vec3(1.0, 1.0, 1.0)),
+        ]);
+        Primitives::render(&Material::default(), &lights);
+    });
+    check("lighting_from_above", image);
+}
+
+#[test]
+fn lighting_colored() {
+    let image = render("lighting_colored", || unsafe {
+        let lights = LightUniforms::new(glm::vec3(0.05, 0.05, 0.05), &[
+            Light::directional(glm::vec3(1.0, -0.5, 0.0), glm::vec3(1.0, 0.3, 0.2)),
+            Light::directional(glm::vec3(-1.0, -0.5, 0.0), glm::vec3(0.2, 0.4, 1.0)),
+            Light::directional(glm::vec3(0.0, -0.3, 1.0), glm::vec3(0.3, 0.8, 0.3)),
+        ]);
+        Primitives::render(&Material::default(), &lights);
+    });
+    check("lighting_colored", image);
+}
+
+#[test]
+#[ignore = "needs the models in ./resources"]
+fn helicopter_at_0s() {
+    helicopter_at("helicopter_at_0s", 0.0);
+}
+
+#[test]
+#[ignore = "needs the models in ./resources"]
+fn helicopter_at_1s() {
+    helicopter_at("helicopter_at_1s", 1.0);
+}
+
+#[test]
+#[ignore = "needs the models in ./resources"]
+fn helicopter_at_2_5s() {
+    helicopter_at("helicopter_at_2_5s", 2.5);
+}
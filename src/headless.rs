@@ -0,0 +1,262 @@
+extern crate nalgebra_glm as glm;
+use std::path::PathBuf;
+use std::ptr;
+
+use khronos_egl as egl;
+
+use crate::camera::{OrbitCamera, Perspective, Projection, ViewPreset};
+use crate::mesh::ColorMode;
+use crate::render_target::RenderTarget;
+use crate::scene::{self, Scene, SceneOptions, TerrainSource, View};
+use crate::util;
+
+// EGL_PLATFORM_SURFACELESS_MESA, a display that needs neither a window system nor a GPU
+const PLATFORM_SURFACELESS_MESA: egl::Enum = 0x31DD;
+
+// The function pointers loaded by `gl::load_with` are global, so tests that make a context take turns
+#[cfg(test)]
+pub static TEST_GL_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+// What to render, set with command line flags
+pub struct HeadlessOptions {
+    pub output          : PathBuf,   // --output render.png
+    pub width           : u32,       // --width 800
+    pub height          : u32,       // --height 600
+    pub time            : f32,       // --time 0, seconds into the animation
+    pub camera_position : glm::Vec3, // --camera-position x,y,z
+    pub camera_target   : glm::Vec3, // --camera-target x,y,z
+    pub perspective     : Perspective, // --fov 0.5 (vertical, in radians), --near 1, --far 1000 (or inf), --reverse-z
+    pub preset          : Option<ViewPreset>, // --top-down or --isometric, orthographic around the camera target
+    pub ortho_height    : Option<f32>, // --ortho-height, how much of the world an orthographic view fits from bottom to top
+    pub color_mode      : Option<ColorMode>, // --color-mode, one of ColorMode::NAMES
+    pub scene           : SceneOptions,
+}
+
+// An OpenGL context without a window, made current on the calling thread for as long as it lives
+pub struct HeadlessContext {
+    egl     : egl::DynamicInstance<egl::EGL1_4>,
+    display : egl::Display,
+    context : egl::Context,
+    surface : Option<egl::Surface>,
+}
+
+impl Default for HeadlessOptions {
+    fn default() -> Self {
+        HeadlessOptions {
+            output          : PathBuf::from("render.png"),
+            width           : crate::SCREEN_W,
+            height          : crate::SCREEN_H,
+            time            : 0.0,
+            camera_position : glm::vec3(20.0, 8.0, 35.0),
+            camera_target   : glm::vec3(0.0, 0.0, 5.0), // Where the helicopter starts its circuit
+            perspective     : Perspective::default(),
+            preset          : None,
+            ortho_height    : None,
+            color_mode      : None,
+            scene           : SceneOptions { terrain: TerrainSource::Model },
+        }
+    }
+}
+
+fn parse_vec3(flag: &str, value: &str) -> Result<glm::Vec3, String> {
+    let components = value.split(',')
+        .map(|c| c.trim().parse::<f32>())
+        .collect::<Result<Vec<f32>, _>>()
+        .map_err(|e| format!("{}: {}", flag, e))?;
+    match components[..] {
+        [x, y, z] => Ok(glm::vec3(x, y, z)),
+        _ => Err(format!("{}: Expected x,y,z, got {}", flag, value)),
+    }
+}
+
+impl HeadlessOptions {
+    // Parse the command line, starting with the program name. Besides the headless flags, only the flags that
+    // choose the scene are allowed, so a mistyped flag is an error rather than a render with the defaults.
+    pub fn from_args(args: &[String]) -> Result<HeadlessOptions, String> {
+        let mut options = HeadlessOptions {
+            scene: SceneOptions::from_args(args),
+            ..Default::default()
+        };
+
+        let mut args = args.iter().skip(1);
+        while let Some(flag) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{}: Missing value", flag));
+            match flag.as_str() {
+                "--output" => options.output = PathBuf::from(value()?),
+                "--width" => options.width = value()?.parse().map_err(|e| format!("{}: {}", flag, e))?,
+                "--height" => options.height = value()?.parse().map_err(|e| format!("{}: {}", flag, e))?,
+                "--time" => options.time = value()?.parse().map_err(|e| format!("{}: {}", flag, e))?,
+                "--fov" => options.perspective.fov = value()?.parse().map_err(|e| format!("{}: {}", flag, e))?,
+                "--near" => options.perspective.near = value()?.parse().map_err(|e| format!("{}: {}", flag, e))?,
+                "--far" => options.perspective.far = value()?.parse().map_err(|e| format!("{}: {}", flag, e))?,
+                "--reverse-z" => options.perspective.reverse_z = true,
+                "--top-down" => options.preset = Some(ViewPreset::TopDown),
+                "--isometric" => options.preset = Some(ViewPreset::Isometric),
+                "--ortho-height" => options.ortho_height = Some(value()?.parse().map_err(|e| format!("{}: {}", flag, e))?),
+                "--camera-position" => options.camera_position = parse_vec3(flag, value()?)?,
+                "--camera-target" => options.camera_target = parse_vec3(flag, value()?)?,
+                "--color-mode" => {
+                    let name = value()?;
+                    options.color_mode = Some(ColorMode::from_name(name).ok_or_else(|| {
+                        format!("{}: Unknown color mode {}, expected one of {}", flag, name, ColorMode::NAMES.join(", "))
+                    })?);
+                },
+                "--headless" | "--streamed-terrain" | "--no-terrain" => { },
+                _ => return Err(format!("Unknown flag {}", flag)),
+            }
+        }
+        if options.width == 0 || options.height == 0 {
+            return Err(format!("Can't render an image of {}x{}", options.width, options.height));
+        }
+        Ok(options)
+    }
+}
+
+impl HeadlessContext {
+    // Prefers Mesa's surfaceless platform, and falls back to a pbuffer on the default display
+    pub fn new() -> Result<HeadlessContext, String> {
+        let egl = unsafe { egl::DynamicInstance::<egl::EGL1_4>::load_required() }
+            .map_err(|e| format!("Could not load libEGL: {}", e))?;
+
+        let surfaceless = egl.upcast::<egl::EGL1_5>()
+            .and_then(|egl| egl.get_platform_display(PLATFORM_SURFACELESS_MESA, egl::DEFAULT_DISPLAY, &[egl::ATTRIB_NONE]).ok())
+            .filter(|&display| egl.initialize(display).is_ok());
+        let (display, needs_surface) = match surfaceless {
+            Some(display) => (display, false),
+            None => {
+                let display = egl.get_display(egl::DEFAULT_DISPLAY).ok_or("No EGL display available")?;
+                egl.initialize(display).map_err(|e| format!("Could not initialize EGL: {}", e))?;
+                (display, true)
+            },
+        };
+
+        egl.bind_api(egl::OPENGL_API).map_err(|e| format!("EGL has no desktop OpenGL: {}", e))?;
+        let config = egl.choose_first_config(display, &[
+            egl::SURFACE_TYPE, egl::PBUFFER_BIT,
+            egl::RENDERABLE_TYPE, egl::OPENGL_BIT,
+            egl::NONE,
+        ]).map_err(|e| e.to_string())?.ok_or("No EGL config supports OpenGL")?;
+        let context = egl.create_context(display, config, None, &[
+            egl::CONTEXT_MAJOR_VERSION, 4,
+            egl::CONTEXT_MINOR_VERSION, 3,
+            egl::CONTEXT_OPENGL_PROFILE_MASK, egl::CONTEXT_OPENGL_CORE_PROFILE_BIT,
+            egl::NONE,
+        ]).map_err(|e| format!("Could not create an OpenGL 4.3 context: {}", e))?;
+
+        // Everything is drawn into a framebuffer object, so the surface is never used
+        let surface = if needs_surface {
+            Some(egl.create_pbuffer_surface(display, config, &[egl::WIDTH, 1, egl::HEIGHT, 1, egl::NONE])
+                .map_err(|e| format!("Could not create a pbuffer: {}", e))?)
+        } else {
+            None
+        };
+        egl.make_current(display, surface, surface, Some(context)).map_err(|e| e.to_string())?;
+
+        gl::load_with(|symbol| egl.get_proc_address(symbol).map_or(ptr::null(), |f| f as *const _));
+        Ok(HeadlessContext { egl, display, context, surface })
+    }
+}
+
+impl Drop for HeadlessContext {
+    fn drop(&mut self) {
+        let _ = self.egl.make_current(self.display, None, None, None);
+        if let Some(surface) = self.surface {
+            let _ = self.egl.destroy_surface(self.display, surface);
+        }
+        let _ = self.egl.destroy_context(self.display, self.context);
+        let _ = self.egl.terminate(self.display);
+    }
+}
+
+// Render one frame of the scene as described by the options, and save it as an image
+pub fn render(options: &HeadlessOptions) -> Result<(), String> {
+    let _context = HeadlessContext::new()?;
+
+    let image = unsafe {
+        scene::setup_gl_state();
+        gl::Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
+        gl::DebugMessageCallback(Some(util::debug_callback), ptr::null());
+        println!("{}: {}", util::get_gl_string(gl::VENDOR), util::get_gl_string(gl::RENDERER));
+
+        let target = RenderTarget::new(options.width, options.height)?;
+        target.bind();
+
+        let mut scene = Scene::new(&options.scene);
+        scene.animate(options.time);
+        if let Some(color_mode) = &options.color_mode {
+            scene.color_terrain(color_mode)?;
+        }
+
+        let aspect = options.width as f32 / options.height as f32;
+        let view = match options.preset {
+            Some(preset) => {
+                // Orbit the target at the distance the camera is from it, from the preset's angle
+                let mut camera = OrbitCamera {
+                    target: options.camera_target,
+                    distance: glm::distance(&options.camera_position, &options.camera_target),
+                    max_distance: f32::INFINITY,
+                    ..Default::default()
+                };
+                camera.set_preset(preset, &options.perspective);
+                if let (Some(ortho_height), Projection::Orthographic { height, .. }) = (options.ortho_height, &mut camera.projection) {
+                    *height = ortho_height;
+                }
+                View {
+                    view: camera.view(),
+                    projection: camera.projection.matrix(aspect),
+                    camera_position: camera.position(),
+                    reverse_z: camera.projection.reverse_z(),
+                }
+            },
+            None => {
+                let projection = Projection::Perspective(options.perspective);
+                View {
+                    view: glm::look_at(&options.camera_position, &options.camera_target, &glm::vec3(0.0, 1.0, 0.0)),
+                    projection: projection.matrix(aspect),
+                    camera_position: options.camera_position,
+                    reverse_z: projection.reverse_z(),
+                }
+            },
+        };
+        scene.load_terrain(&view.camera_position);
+        scene.render(&view, options.time);
+        target.read_pixels()
+    };
+
+    image.save(&options.output).map_err(|e| format!("Could not save {}: {}", options.output.display(), e))?;
+    println!("Saved {}", options.output.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_args(args: &str) -> Result<HeadlessOptions, String> {
+        let args: Vec<String> = std::iter::once("gloom-rs").chain(args.split_whitespace()).map(String::from).collect();
+        HeadlessOptions::from_args(&args)
+    }
+
+    #[test]
+    fn parses_headless_and_scene_flags() {
+        let options = from_args("--headless --streamed-terrain --width 1920 --height 1080 --time 2.5 --camera-target 0,1,2").unwrap();
+        assert_eq!((options.width, options.height, options.time), (1920, 1080, 2.5));
+        assert_eq!(options.camera_target, glm::vec3(0.0, 1.0, 2.0));
+        assert!(options.scene.terrain == TerrainSource::Streamed);
+    }
+
+    #[test]
+    fn rejects_unknown_flags() {
+        assert_eq!(from_args("--headless --widht 1920").err().unwrap(), "Unknown flag --widht");
+        assert_eq!(from_args("--headless render.png").err().unwrap(), "Unknown flag render.png");
+    }
+
+    #[test]
+    fn rejects_bad_values() {
+        assert_eq!(from_args("--headless --width").err().unwrap(), "--width: Missing value");
+        assert!(from_args("--headless --width wide").is_err());
+        assert!(from_args("--headless --camera-position 1,2").is_err());
+        assert!(from_args("--headless --color-mode rainbow").is_err());
+        assert!(from_args("--headless --width 0").is_err());
+    }
+}
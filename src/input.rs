@@ -0,0 +1,370 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::mpsc;
+
+use glutin::event::{MouseButton, MouseScrollDelta, VirtualKeyCode};
+
+// Something that is either down or up
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Button {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+    Gamepad(GamepadButton),
+}
+
+// Something that moves by some amount each frame, or that is held at some position
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Axis {
+    MouseX, // Pixels, to the right
+    MouseY, // Pixels, down like on screen
+    Wheel,  // Scroll wheel steps, away from the user
+    Gamepad(GamepadAxis), // A position, from -1 to 1 for sticks and 0 to 1 for triggers
+}
+
+// Sent to the thread reading the input, from the event loop and from gamepads
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum InputEvent {
+    Pressed(Button),
+    Released(Button),
+    Moved(Axis, f32),         // By an amount, for the mouse and scroll wheel
+    AxisChanged(Axis, f32),   // To a position, for gamepad sticks and triggers
+}
+
+// An enum whose variants go by names in the bindings file
+macro_rules! named {
+    ($(#[$meta:meta])* pub enum $enum:ident { $($variant:ident = $name:literal,)* }) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+        pub enum $enum {
+            $($variant,)*
+        }
+
+        impl $enum {
+            #[allow(dead_code)]
+            pub const ALL: &'static [$enum] = &[$($enum::$variant,)*];
+
+            pub fn from_name(name: &str) -> Option<$enum> {
+                match name {
+                    $($name => Some($enum::$variant),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+named! {
+    // The buttons of a gamepad, named by where they are rather than what they are labelled
+    pub enum GamepadButton {
+        South       = "PadSouth",
+        East        = "PadEast",
+        North       = "PadNorth",
+        West        = "PadWest",
+        LeftBumper  = "PadLeftBumper",
+        RightBumper = "PadRightBumper",
+        LeftStick   = "PadLeftStick", // Pressing the stick in
+        RightStick  = "PadRightStick",
+        Select      = "PadSelect",
+        Start       = "PadStart",
+        Up          = "PadUp",
+        Down        = "PadDown",
+        Left        = "PadLeft",
+        Right       = "PadRight",
+    }
+}
+
+named! {
+    // The analog inputs of a gamepad. Up and right are positive on the sticks.
+    pub enum GamepadAxis {
+        LeftX        = "PadLeftX",
+        LeftY        = "PadLeftY",
+        RightX       = "PadRightX",
+        RightY       = "PadRightY",
+        LeftTrigger  = "PadLeftTrigger",
+        RightTrigger = "PadRightTrigger",
+    }
+}
+
+named! {
+    // What the inputs are bound to
+    pub enum Action {
+        MoveRight        = "move_right",
+        MoveForward      = "move_forward",
+        MoveUp           = "move_up",
+        TurnRight        = "turn_right",
+        TurnUp           = "turn_up",
+        LookX            = "look_x",
+        LookY            = "look_y",
+        Zoom             = "zoom",
+        GrabCursor       = "grab_cursor",
+        Crater           = "crater",
+        ColorMode        = "color_mode",
+        Lighting         = "lighting",
+        Screenshot       = "screenshot",
+        Record           = "record",
+        CycleCamera      = "cycle_camera",
+        ToggleProjection = "toggle_projection",
+        TopDown          = "top_down",
+        Isometric        = "isometric",
+        Quit             = "quit",
+        ToggleFlight     = "toggle_flight",
+        Collective       = "collective",
+        CyclicX          = "cyclic_x",
+        CyclicY          = "cyclic_y",
+        Pedals           = "pedals",
+    }
+}
+
+// Keys by the names of their VirtualKeyCode variants
+macro_rules! key_names {
+    ($($key:ident)*) => {
+        fn key_from_name(name: &str) -> Option<VirtualKeyCode> {
+            match name {
+                $(stringify!($key) => Some(VirtualKeyCode::$key),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+key_names! {
+    A B C D E F G H I J K L M N O P Q R S T U V W X Y Z
+    Key0 Key1 Key2 Key3 Key4 Key5 Key6 Key7 Key8 Key9
+    F1 F2 F3 F4 F5 F6 F7 F8 F9 F10 F11 F12
+    Numpad0 Numpad1 Numpad2 Numpad3 Numpad4 Numpad5 Numpad6 Numpad7 Numpad8 Numpad9
+    Escape Space Return Tab Back Insert Delete Home End PageUp PageDown
+    Left Right Up Down
+    LShift RShift LControl RControl LAlt RAlt
+    Minus Equals Comma Period Slash Semicolon Apostrophe LBracket RBracket Backslash Grave
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Source {
+    Button(Button),
+    Axis(Axis),
+}
+
+// An input feeding an action, scaled by -1 when written with a leading minus
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Binding {
+    source : Source,
+    scale  : f32,
+}
+
+// Which inputs drive which actions
+#[derive(Clone, PartialEq, Debug)]
+pub struct Bindings {
+    actions: HashMap<Action, Vec<Binding>>,
+}
+
+// The state of every action for the current frame, fed by events from the event loop
+pub struct Input {
+    bindings : Bindings,
+    events   : mpsc::Receiver<InputEvent>,
+    held     : HashSet<Button>,
+    pressed  : HashSet<Button>, // Went down since last frame
+    released : HashSet<Button>, // Went up since last frame
+    axes     : HashMap<Axis, f32>, // Movement since last frame
+    positions: HashMap<Axis, f32>, // Where the axes that stay put are
+}
+
+fn parse_source(name: &str) -> Option<Source> {
+    let source = match name {
+        "MouseX" => Source::Axis(Axis::MouseX),
+        "MouseY" => Source::Axis(Axis::MouseY),
+        "Wheel" => Source::Axis(Axis::Wheel),
+        "MouseLeft" => Source::Button(Button::Mouse(MouseButton::Left)),
+        "MouseRight" => Source::Button(Button::Mouse(MouseButton::Right)),
+        "MouseMiddle" => Source::Button(Button::Mouse(MouseButton::Middle)),
+        _ => if let Some(button) = GamepadButton::from_name(name) {
+            Source::Button(Button::Gamepad(button))
+        } else if let Some(axis) = GamepadAxis::from_name(name) {
+            Source::Axis(Axis::Gamepad(axis))
+        } else {
+            match name.strip_prefix("Mouse").and_then(|n| n.parse().ok()) {
+                Some(n) => Source::Button(Button::Mouse(MouseButton::Other(n))),
+                None => Source::Button(Button::Key(key_from_name(name)?)),
+            }
+        },
+    };
+    Some(source)
+}
+
+// One line of a bindings file: `action = input, -input`
+fn parse_line(line: &str) -> Result<(Action, Vec<Binding>), String> {
+    let (action, inputs) = line.split_once('=').ok_or("Expected action = inputs")?;
+    let action = Action::from_name(action.trim()).ok_or_else(|| format!("Unknown action {}", action.trim()))?;
+    let bindings = inputs.split(',')
+        .map(str::trim)
+        .filter(|input| !input.is_empty())
+        .map(|input| {
+            let (name, scale) = match input.strip_prefix('-') {
+                Some(name) => (name.trim(), -1.0),
+                None => (input, 1.0),
+            };
+            let source = parse_source(name).ok_or_else(|| format!("Unknown input {}", name))?;
+            Ok(Binding { source, scale })
+        })
+        .collect::<Result<Vec<Binding>, String>>()?;
+    Ok((action, bindings))
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let mut bindings = Bindings { actions: HashMap::new() };
+        for line in DEFAULT_BINDINGS.lines() {
+            let (action, inputs) = parse_line(line).unwrap();
+            bindings.actions.insert(action, inputs);
+        }
+        bindings
+    }
+}
+
+// The same as bindings.cfg
+const DEFAULT_BINDINGS: &str = "\
+move_right = D, -A
+move_forward = W, -S
+move_up = E, -Q
+turn_right = Right, -Left
+turn_up = Up, -Down
+look_x = MouseX
+look_y = MouseY
+zoom = Wheel
+grab_cursor = G
+crater = C
+color_mode = M
+lighting = L
+screenshot = F12
+record = F10
+cycle_camera = V, PadNorth
+toggle_projection = P
+top_down = T
+isometric = I
+quit = Escape
+toggle_flight = H, PadStart
+collective = PadLeftY, PageUp, -PageDown
+cyclic_x = PadRightX, Numpad6, -Numpad4
+cyclic_y = PadRightY, Numpad8, -Numpad2
+pedals = PadLeftX, Numpad9, -Numpad7";
+
+impl Bindings {
+    // The default bindings, with the actions listed in the file bound to what it says instead
+    pub fn load(path: &Path) -> Result<Bindings, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Bindings::parse(&text).map_err(|e| format!("{}:{}", path.display(), e))
+    }
+
+    // Like `load`, from the text of a bindings file. Lines are `action = input, input`, and everything after a #
+    // is a comment. Errors start with the line number.
+    pub fn parse(text: &str) -> Result<Bindings, String> {
+        let mut bindings = Bindings::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (action, inputs) = parse_line(line).map_err(|e| format!("{}: {}", number + 1, e))?;
+            bindings.actions.insert(action, inputs);
+        }
+        Ok(bindings)
+    }
+
+    // Whether `button` is one of the inputs of `action`
+    pub fn is_bound(&self, action: Action, button: Button) -> bool {
+        self.get(action).iter().any(|binding| binding.source == Source::Button(button))
+    }
+
+    fn get(&self, action: Action) -> &[Binding] {
+        self.actions.get(&action).map_or(&[], |bindings| bindings.as_slice())
+    }
+}
+
+impl InputEvent {
+    // Scroll wheel steps, counting touchpad scrolling in pixels as a step per 20 pixels
+    pub fn scroll(delta: MouseScrollDelta) -> InputEvent {
+        let steps = match delta {
+            MouseScrollDelta::LineDelta(_, y) => y,
+            MouseScrollDelta::PixelDelta(position) => position.y as f32 / 20.0,
+        };
+        InputEvent::Moved(Axis::Wheel, steps)
+    }
+}
+
+impl Input {
+    // Input fed by the other end of the channel
+    pub fn new(bindings: Bindings) -> (Input, mpsc::Sender<InputEvent>) {
+        let (sender, events) = mpsc::channel();
+        let input = Input {
+            bindings,
+            events,
+            held     : HashSet::new(),
+            pressed  : HashSet::new(),
+            released : HashSet::new(),
+            axes     : HashMap::new(),
+            positions: HashMap::new(),
+        };
+        (input, sender)
+    }
+
+    // Take in the events sent since last frame. Call this once at the start of every frame.
+    pub fn update(&mut self) {
+        self.pressed.clear();
+        self.released.clear();
+        self.axes.clear();
+        while let Ok(event) = self.events.try_recv() {
+            self.handle(event);
+        }
+    }
+
+    fn handle(&mut self, event: InputEvent) {
+        match event {
+            // Key repeat sends more presses while the key is held, which aren't new presses
+            InputEvent::Pressed(button) => if self.held.insert(button) {
+                self.pressed.insert(button);
+            },
+            InputEvent::Released(button) => if self.held.remove(&button) {
+                self.released.insert(button);
+            },
+            InputEvent::Moved(axis, amount) => *self.axes.entry(axis).or_insert(0.0) += amount,
+            InputEvent::AxisChanged(axis, position) => {
+                self.positions.insert(axis, position);
+            },
+        }
+    }
+
+    fn any_button(&self, action: Action, buttons: &HashSet<Button>) -> bool {
+        self.bindings.get(action).iter().any(|binding| match binding.source {
+            Source::Button(button) => buttons.contains(&button),
+            Source::Axis(_) => false,
+        })
+    }
+
+    // Whether any button of the action is down
+    #[allow(dead_code)]
+    pub fn held(&self, action: Action) -> bool {
+        self.any_button(action, &self.held)
+    }
+
+    // Whether a button of the action went down since last frame, even if it has already been let go
+    pub fn pressed(&self, action: Action) -> bool {
+        self.any_button(action, &self.pressed)
+    }
+
+    // Whether a button of the action went up since last frame
+    #[allow(dead_code)]
+    pub fn released(&self, action: Action) -> bool {
+        self.any_button(action, &self.released)
+    }
+
+    // Held buttons count as 1, mouse axes as how far they moved since last frame, and gamepad axes as where
+    // they are, each scaled by its binding
+    pub fn value(&self, action: Action) -> f32 {
+        self.bindings.get(action).iter().map(|binding| {
+            let amount = match binding.source {
+                Source::Button(button) => if self.held.contains(&button) { 1.0 } else { 0.0 },
+                Source::Axis(axis) => self.axes.get(&axis).or_else(|| self.positions.get(&axis)).copied().unwrap_or(0.0),
+            };
+            amount * binding.scale
+        }).sum()
+    }
+}
@@ -0,0 +1,174 @@
+// Input tests: events are fed to the input the way the event loop and a gamepad would, with a virtual
+// gamepad standing in for a real one, and the actions and flight controls they make are checked.
+extern crate nalgebra_glm as glm;
+use std::path::Path;
+
+use glutin::event::{MouseButton, VirtualKeyCode};
+
+use crate::flight::{Flight, FlightControls};
+use crate::gamepad::VirtualGamepad;
+use crate::input::{Action, Axis, Bindings, Button, GamepadAxis, GamepadButton, Input, InputEvent};
+
+const FRAME: f32 = 1.0 / 60.0;
+
+fn key(key: VirtualKeyCode) -> Button {
+    Button::Key(key)
+}
+
+fn controls(input: &Input) -> FlightControls {
+    FlightControls {
+        collective: input.value(Action::Collective),
+        cyclic: glm::vec2(input.value(Action::CyclicX), input.value(Action::CyclicY)),
+        pedals: input.value(Action::Pedals),
+    }
+}
+
+// Fly for `seconds` with whatever the input says
+fn fly(input: &mut Input, flight: &mut Flight, seconds: f32) {
+    for _ in 0..(seconds / FRAME) as u32 {
+        input.update();
+        flight.update(&controls(input), Some(-100.0), FRAME);
+    }
+}
+
+#[test]
+fn bindings_file_matches_defaults() {
+    assert_eq!(Bindings::load(Path::new("bindings.cfg")).unwrap(), Bindings::default());
+}
+
+#[test]
+fn bindings_file_overrides_listed_actions() {
+    let bindings = Bindings::parse("# Comment\nmove_forward = Up, -Down  # Arrows\nquit = MouseMiddle\n").unwrap();
+    assert!(bindings.is_bound(Action::MoveForward, key(VirtualKeyCode::Up)));
+    assert!(!bindings.is_bound(Action::MoveForward, key(VirtualKeyCode::W)));
+    assert!(bindings.is_bound(Action::Quit, Button::Mouse(MouseButton::Middle)));
+    assert!(!bindings.is_bound(Action::Quit, key(VirtualKeyCode::Escape)));
+    assert!(bindings.is_bound(Action::Screenshot, key(VirtualKeyCode::F12)));
+}
+
+#[test]
+fn bindings_file_errors_name_the_line() {
+    let error = Bindings::parse("move_right = D\njump = Space\n").unwrap_err();
+    assert_eq!(error, "2: Unknown action jump");
+    let error = Bindings::parse("move_right = Dee\n").unwrap_err();
+    assert_eq!(error, "1: Unknown input Dee");
+
+    // Loading a file puts its name in front
+    let error = Bindings::load(Path::new("no-such-bindings.cfg")).unwrap_err();
+    assert!(error.starts_with("no-such-bindings.cfg: "), "{}", error);
+}
+
+#[test]
+fn held_pressed_and_released() {
+    let (mut input, events) = Input::new(Bindings::default());
+    events.send(InputEvent::Pressed(key(VirtualKeyCode::G))).unwrap();
+    input.update();
+    assert!(input.pressed(Action::GrabCursor) && input.held(Action::GrabCursor));
+
+    // Key repeat sends the press again, which doesn't make it pressed again
+    events.send(InputEvent::Pressed(key(VirtualKeyCode::G))).unwrap();
+    input.update();
+    assert!(!input.pressed(Action::GrabCursor) && input.held(Action::GrabCursor));
+
+    events.send(InputEvent::Released(key(VirtualKeyCode::G))).unwrap();
+    input.update();
+    assert!(input.released(Action::GrabCursor) && !input.held(Action::GrabCursor));
+    input.update();
+    assert!(!input.released(Action::GrabCursor));
+
+    // A tap within a single frame is still seen
+    events.send(InputEvent::Pressed(key(VirtualKeyCode::F12))).unwrap();
+    events.send(InputEvent::Released(key(VirtualKeyCode::F12))).unwrap();
+    input.update();
+    assert!(input.pressed(Action::Screenshot) && input.released(Action::Screenshot) && !input.held(Action::Screenshot));
+}
+
+#[test]
+fn values_add_up_bindings() {
+    let (mut input, events) = Input::new(Bindings::default());
+    events.send(InputEvent::Pressed(key(VirtualKeyCode::A))).unwrap();
+    input.update();
+    assert_eq!(input.value(Action::MoveRight), -1.0);
+    events.send(InputEvent::Pressed(key(VirtualKeyCode::D))).unwrap();
+    input.update();
+    assert_eq!(input.value(Action::MoveRight), 0.0);
+
+    // The mouse moves by an amount each frame, which is gone the next
+    events.send(InputEvent::Moved(Axis::MouseX, 3.0)).unwrap();
+    events.send(InputEvent::Moved(Axis::MouseX, 4.0)).unwrap();
+    input.update();
+    assert_eq!(input.value(Action::LookX), 7.0);
+    input.update();
+    assert_eq!(input.value(Action::LookX), 0.0);
+}
+
+#[test]
+fn virtual_gamepad_holds_axes_until_moved() {
+    let (mut input, events) = Input::new(Bindings::default());
+    let gamepad = VirtualGamepad::new(events);
+    gamepad.set_axis(GamepadAxis::LeftY, 0.5);
+    gamepad.press(GamepadButton::Start);
+    input.update();
+    assert_eq!(input.value(Action::Collective), 0.5);
+    assert!(input.pressed(Action::ToggleFlight));
+
+    input.update();
+    assert_eq!(input.value(Action::Collective), 0.5);
+    assert!(input.held(Action::ToggleFlight) && !input.pressed(Action::ToggleFlight));
+
+    gamepad.disconnect();
+    input.update();
+    assert_eq!(input.value(Action::Collective), 0.0);
+    assert!(input.released(Action::ToggleFlight));
+}
+
+#[test]
+fn gamepad_flies_the_helicopter() {
+    let (mut input, events) = Input::new(Bindings::default());
+    let gamepad = VirtualGamepad::new(events);
+    let mut flight = Flight::default();
+
+    // Centered controls hover in place
+    fly(&mut input, &mut flight, 1.0);
+    assert!(glm::length(&flight.position) < 1e-4, "{:?}", flight.position);
+
+    // Collective up climbs
+    gamepad.set_axis(GamepadAxis::LeftY, 1.0);
+    fly(&mut input, &mut flight, 1.0);
+    assert!(flight.position.y > 5.0, "{:?}", flight.position);
+    gamepad.set_axis(GamepadAxis::LeftY, 0.0);
+
+    // Cyclic forward dips the nose and flies towards it, which is down the negative z-axis
+    gamepad.set_axis(GamepadAxis::RightY, 1.0);
+    fly(&mut input, &mut flight, 2.0);
+    assert!(flight.pitch < 0.0);
+    assert!(flight.position.z < -10.0 && flight.position.x.abs() < 1e-3, "{:?}", flight.position);
+    gamepad.set_axis(GamepadAxis::RightY, 0.0);
+
+    // Cyclic right lowers the right side and drifts right
+    let before = flight.position;
+    gamepad.set_axis(GamepadAxis::RightX, 1.0);
+    fly(&mut input, &mut flight, 2.0);
+    assert!(flight.roll < 0.0);
+    assert!(flight.position.x > before.x + 5.0, "{:?}", flight.position);
+    gamepad.set_axis(GamepadAxis::RightX, 0.0);
+
+    // Right pedal turns the nose right, towards the positive x-axis from the start
+    let yaw = flight.yaw;
+    gamepad.set_axis(GamepadAxis::LeftX, 1.0);
+    fly(&mut input, &mut flight, 0.5);
+    assert!(flight.yaw < yaw && flight.forward().x > 0.0);
+}
+
+#[test]
+fn helicopter_stays_above_ground() {
+    let (mut input, events) = Input::new(Bindings::default());
+    let gamepad = VirtualGamepad::new(events);
+    let mut flight = Flight::default();
+    gamepad.set_axis(GamepadAxis::LeftY, -1.0);
+    for _ in 0..120 {
+        input.update();
+        flight.update(&controls(&input), Some(-3.0), FRAME);
+    }
+    assert_eq!(flight.position.y, -3.0 + flight.clearance);
+}
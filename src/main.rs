@@ -1,122 +1,120 @@
 extern crate nalgebra_glm as glm;
-use std::{ mem, ptr, os::raw::c_void };
+use std::ptr;
 use std::thread;
 use std::sync::{Mutex, Arc, RwLock};
 
+mod gl_object;
+mod vao;
 mod shader;
+mod shader_library;
+mod program_cache;
+mod preprocessor;
+mod reflection;
+mod uniform;
+mod compute;
+mod uniform_buffer;
 mod util;
 mod mesh;
 mod scene_graph;
-use scene_graph::SceneNode;
-
-use glutin::event::{Event, WindowEvent, DeviceEvent, KeyboardInput, ElementState::{Pressed, Released}, VirtualKeyCode::{self, *}};
+mod terrain;
+mod chunked_terrain;
+mod toolbox;
+mod scene;
+use scene::{Scene, SceneOptions, View};
+mod render_target;
+mod headless;
+mod capture;
+mod camera;
+mod input;
+use input::{Action, Bindings, Button, Input, InputEvent, Axis};
+mod gamepad;
+mod flight;
+use flight::{Flight, FlightControls};
+use camera::{Camera, CameraMode, ChaseCamera, OrbitCamera, Perspective, Projection, ViewPreset};
+#[cfg(test)]
+mod golden_tests;
+#[cfg(test)]
+mod input_tests;
+
+use glutin::event::{Event, WindowEvent, DeviceEvent, KeyboardInput, ElementState::{Pressed, Released}};
 use glutin::event_loop::ControlFlow;
 
+use mesh::ColorMode;
+use uniform_buffer::LightUniforms;
+use chunked_terrain::ChunkedTerrain;
+
 const SCREEN_W: u32 = 800;
 const SCREEN_H: u32 = 600;
 
-// == // Helper functions to make interacting with OpenGL a little bit prettier. You *WILL* need these! // == //
-// The names should be pretty self explanatory
-fn byte_size_of_array<T>(val: &[T]) -> isize {
-    std::mem::size_of_val(&val[..]) as isize
-}
-
-// Get the OpenGL-compatible pointer to an arbitrary array of numbers
-fn pointer_to_array<T>(val: &[T]) -> *const c_void {
-    &val[0] as *const T as *const c_void
-}
-
-// Get the size of the given type in bytes
-fn size_of<T>() -> i32 {
-    mem::size_of::<T>() as i32
+// The color mode passed with --color-mode, as an index into ColorMode::NAMES
+fn color_mode_from_args(args: &[String]) -> Result<Option<usize>, String> {
+    let name = match args.iter().position(|arg| arg == "--color-mode") {
+        Some(i) => args.get(i + 1).ok_or("--color-mode needs one of the color modes")?,
+        None => return Ok(None),
+    };
+    let i = ColorMode::NAMES.iter().position(|n| n == name)
+        .ok_or_else(|| format!("Unknown color mode {}, expected one of {}", name, ColorMode::NAMES.join(", ")))?;
+    if args.iter().any(|arg| arg == "--streamed-terrain") && !ChunkedTerrain::supports(&ColorMode::from_name(name).unwrap()) {
+        return Err(format!("Streamed terrain can't be colored by {}", name));
+    }
+    Ok(Some(i))
 }
 
-// Get an offset in bytes for n units of type T
-fn offset<T>(n: u32) -> *const c_void {
-    (n * mem::size_of::<T>() as u32) as *const T as *const c_void
+// Confine the cursor to the window and hide it, or let it go. Returns whether it ended up grabbed.
+fn set_cursor_grab(window: &glutin::window::Window, grab: bool) -> bool {
+    if let Err(e) = window.set_cursor_grab(grab) {
+        println!("Could not grab the cursor: {}", e);
+        return false;
+    }
+    window.set_cursor_visible(!grab);
+    grab
 }
 
-// Get a null pointer (equivalent to an offset of 0)
-// ptr::null()
-
-
-
-// == // Modify and complete the function below for the first task
-unsafe fn setup_vao(vertices: &Vec<f32>, indices: &Vec<u32>, color: &Vec<f32>, normals: &Vec<f32>) -> u32 {
-    //Declare the VAO and VBO
-    let mut VAO: u32 = 0;
-    let mut vertex_VBO: u32 = 0;
-    let mut index_VBO: u32 = 0;
-    let mut color_VBO: u32 = 0;
-    let mut normals_VBO: u32 = 0;
-
-
-    // Generate vertex arrays and buffers
-    gl::GenVertexArrays(1, &mut VAO);
-    gl::GenBuffers(1, &mut vertex_VBO);
-
-    // Binds the vertex array
-    gl::BindVertexArray(VAO);
-
-
-    gl::BindBuffer(gl::ARRAY_BUFFER, vertex_VBO);
-    gl::BufferData(gl::ARRAY_BUFFER, byte_size_of_array(&vertices), pointer_to_array(&vertices), gl::STATIC_DRAW);
-    
-
-    // Generate buffer for indices
-    gl::GenBuffers(1, &mut index_VBO);
-
-    gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, index_VBO);
-    gl::BufferData(gl::ELEMENT_ARRAY_BUFFER, byte_size_of_array(&indices), pointer_to_array(&indices), gl::STATIC_DRAW);
-
-    gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, 3 * size_of::<f32>(), ptr::null());
-
-    gl::EnableVertexAttribArray(0);
-
-
-    // Generate buffer for color
-    gl::GenBuffers(1, &mut color_VBO);
-    gl::BindBuffer(gl::ARRAY_BUFFER, color_VBO);
-    gl::BufferData(gl::ARRAY_BUFFER, byte_size_of_array(&color), pointer_to_array(&color), gl::STATIC_DRAW);
-
-    gl::VertexAttribPointer(1, 4, gl::FLOAT, gl::FALSE, 4 * size_of::<f32>(), ptr::null());
-
-    gl::EnableVertexAttribArray(1);
-
-    //Generate buffer for normals
-    gl::GenBuffers(1, &mut normals_VBO);
-    gl::BindBuffer(gl::ARRAY_BUFFER, normals_VBO);
-    gl::BufferData(gl::ARRAY_BUFFER, byte_size_of_array(&normals), pointer_to_array(&normals), gl::STATIC_DRAW);
-
-    gl::VertexAttribPointer(5, 3, gl::FLOAT, gl::FALSE, 3 * size_of::<f32>(), ptr::null());
-    gl::EnableVertexAttribArray(5);
+fn main() {
+    // Pass --headless to render a single frame to an image instead of opening a window
+    let args: Vec<String> = std::env::args().collect();
+    if args.iter().any(|arg| arg == "--headless") {
+        let result = headless::HeadlessOptions::from_args(&args).and_then(|options| headless::render(&options));
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
 
-    return VAO;
-} 
+    // Pass --color-mode with one of ColorMode::NAMES to start out with the terrain colored by it
+    let mut color_mode = color_mode_from_args(&args).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
 
-fn main() {
     // Set up the necessary objects to deal with windows and event handling
     let el = glutin::event_loop::EventLoop::new();
     let wb = glutin::window::WindowBuilder::new()
         .with_title("Gloom-rs")
-        .with_resizable(false)
         .with_inner_size(glutin::dpi::LogicalSize::new(SCREEN_W, SCREEN_H));
     let cb = glutin::ContextBuilder::new()
         .with_vsync(true);
     let windowed_context = cb.build_windowed(wb, &el).unwrap();
-    // Uncomment these if you want to use the mouse for controls, but want it to be confined to the screen and/or invisible.
-    // windowed_context.window().set_cursor_grab(true).expect("failed to grab cursor");
-    // windowed_context.window().set_cursor_visible(false);
-
-    // Set up a shared vector for keeping track of currently pressed keys
-    let arc_pressed_keys = Arc::new(Mutex::new(Vec::<VirtualKeyCode>::with_capacity(10)));
-    // Make a reference of this vector to send to the render thread
-    let pressed_keys = Arc::clone(&arc_pressed_keys);
 
-    // Set up shared tuple for tracking mouse movement between frames
-    let arc_mouse_delta = Arc::new(Mutex::new((0f32, 0f32)));
-    // Make a reference of this tuple to send to the render thread
-    let mouse_delta = Arc::clone(&arc_mouse_delta);
+    // Keys, mouse buttons and mouse movement are bound to actions by bindings.cfg, or the file passed with --bindings.
+    // The event loop sends input events to the render thread, which works out the state of every action each frame.
+    let bindings_path = util::flag_value(&args, "--bindings").unwrap_or_else(|| std::path::PathBuf::from("bindings.cfg"));
+    let bindings = if bindings_path.exists() {
+        Bindings::load(&bindings_path).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        })
+    } else {
+        Bindings::default()
+    };
+    let (mut input, input_events) = Input::new(bindings.clone());
+    // Gamepads send their input from a thread of their own
+    gamepad::spawn(input_events.clone());
+
+    // Set up a shared slot for the latest size of the window in physical pixels, if it has changed since last frame
+    let arc_resized = Arc::new(Mutex::new(None::<glutin::dpi::PhysicalSize<u32>>));
+    let resized = Arc::clone(&arc_resized);
 
     // Spawn a separate thread for rendering, so event handling doesn't block rendering
     let render_thread = thread::spawn(move || {
@@ -130,12 +128,7 @@ fn main() {
 
         // Set up openGL
         unsafe {
-            gl::Enable(gl::DEPTH_TEST);
-            gl::DepthFunc(gl::LESS);
-            gl::Enable(gl::CULL_FACE);
-            gl::Disable(gl::MULTISAMPLE);
-            gl::Enable(gl::BLEND);
-            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            scene::setup_gl_state();
             gl::Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
             gl::DebugMessageCallback(Some(util::debug_callback), ptr::null());
 
@@ -145,280 +138,275 @@ fn main() {
             println!("GLSL\t: {}", util::get_gl_string(gl::SHADING_LANGUAGE_VERSION));
         }
 
-        // == // Set up your VAO here
-
-        //Vector containing vertices for the triangles
-        let vertex_vector: Vec<f32> = vec![
-            //Task 1
-            // -1.0, -1.0, 0.0,
-            // 0.0, -1.0, 0.0,
-            // -0.5, 0.0, 0.0,
-
-            // 0.0, -1.0, 0.0,
-            // 1.0, -1.0, 0.0,
-            // 0.5, 0.0, 0.0,
-
-            // 0.0, 0.0, 0.0,
-            // 0.5, 1.0, 0.0,
-            // -0.5, 1.0, 0.0
-
-            //Task 2, 4
-            -0.3, -0.3, 0.5, // first triangle
-            0.3, -0.3, 0.5,
-            0.0, 0.7, 0.5,
-
-            -0.6, -0.4, 0.0, // second triangle
-            0.0, -0.4, 0.0,
-            -0.3, 0.4, 0.0,
-
-            -0.5, -0.5, -1.0, // third triangle
-            0.1, -0.5, -1.0,
-            -0.2, 0.1, -1.0,
-
-        ];
-      
-        let indices_array: Vec<u32> = vec![0,1,2,3,4,5,6,7,8];
-        
-        //Task 2b_ii
-        //let indices_array: Vec<u32> = vec![6,7,8,3,4,5,0,1,2];
-
-        let color_array: Vec<f32> = vec![
-            //Task 1
-            // 1.0, 0.0, 0.0, 1.0, // first triangle
-            // 0.0, 1.0, 0.0, 1.0,
-            // 0.0, 0.0, 1.0, 1.0,
-
-            // 0.2, 0.3, 0.3, 1.0, // second triangle
-            // 1.0, 0.0, 0.6, 1.0,
-            // 0.0, 0.8, 0.3, 1.0,
-
-            // 0.5, 0.6, 0.1, 1.0, // third triangle
-            // 0.3, 0.2, 0.8, 1.0,
-            // 0.8, 0.1, 0.5, 1.0,
-
-            //Task 2, 4
-            1.0, 0.0, 0.0, 0.4,
-            1.0, 0.0, 0.0, 0.4,
-            1.0, 0.0, 0.0, 0.4,
-
-            0.0, 1.0, 0.0, 0.4,
-            0.0, 1.0, 0.0, 0.4,
-            0.0, 1.0, 0.0, 0.4,
-
-            0.0, 0.0, 1.0, 0.4,
-            0.0, 0.0, 1.0, 0.4,
-            0.0, 0.0, 1.0, 0.4,
-
-        ];
-
-       
-        //Load models
-        let terrain = mesh::Terrain::load("./resources/lunarsurface.obj");
-        let helicopter = mesh::Helicopter::load("./resources/helicopter.obj");
-
-        //Build vaos
-        let vao_terrain;
-        let vao_body;
-        let vao_door;
-        let vao_main_rotor;
-        let vao_tail_rotor;
-        unsafe {
-            //vao = setup_vao(&vertex_vector, &indices_array, &color_array);
-            vao_terrain = setup_vao(&terrain.vertices, &terrain.indices, &terrain.colors, &terrain.normals);
-            vao_body = setup_vao(&helicopter.body.vertices,&helicopter.body.indices, &helicopter.body.colors, &helicopter.body.normals);
-            vao_door = setup_vao(&helicopter.door.vertices,&helicopter.door.indices, &helicopter.door.colors, &helicopter.door.normals);
-            vao_main_rotor = setup_vao(&helicopter.main_rotor.vertices,&helicopter.main_rotor.indices, &helicopter.main_rotor.colors, &helicopter.main_rotor.normals);
-            vao_tail_rotor = setup_vao(&helicopter.tail_rotor.vertices,&helicopter.tail_rotor.indices, &helicopter.tail_rotor.colors, &helicopter.tail_rotor.normals);
+        // Pass --streamed-terrain to replace the terrain model with generated terrain, streamed in chunks around the camera,
+        // or --no-terrain to leave it out
+        let args: Vec<String> = std::env::args().collect();
+        let scene_options = SceneOptions::from_args(&args);
+        let mut scene = unsafe { Scene::new(&scene_options) };
+        if let Some(i) = color_mode {
+            // Unsupported modes are rejected by color_mode_from_args
+            scene.color_terrain(&ColorMode::from_name(ColorMode::NAMES[i]).unwrap()).unwrap();
+        }
+        // L steps through LightUniforms::PRESET_NAMES, starting out with the first
+        let mut lighting = 0;
+
+        // F12 saves a screenshot, and F10 starts and stops recording every frame at a fixed timestep.
+        // Pass --record to start recording right away, and --record-fps to change the timestep.
+        let mut frame_capture = capture::FrameCapture::new();
+        let record_fps = util::flag_value(&args, "--record-fps").unwrap_or(30.0);
+        let mut recording = if args.iter().any(|arg| arg == "--record") {
+            Some(capture::Recording::new(std::path::Path::new("recordings"), record_fps))
+        } else {
+            None
         };
 
-        
-        let mut root_node = SceneNode::new();
-        let mut terrain_node = SceneNode::from_vao(vao_terrain, terrain.index_count);
-        let mut body_node = SceneNode::from_vao(vao_body, helicopter.body.index_count);
-        let mut door_node = SceneNode::from_vao(vao_door, helicopter.door.index_count);
-        let mut main_rotor_node = SceneNode::from_vao(vao_main_rotor, helicopter.main_rotor.index_count);
-        let mut tail_rotor_node = SceneNode::from_vao(vao_tail_rotor, helicopter.tail_rotor.index_count);
-
-        root_node.add_child(&terrain_node);
-        terrain_node.add_child(&body_node);
-        body_node.add_child(&door_node);
-        body_node.add_child(&main_rotor_node);
-        body_node.add_child(&tail_rotor_node);
-    
-        body_node.print();
-
-        body_node.position = glm::vec3(0.0, 00.0, -40.0);
-        body_node.rotation.y = 3.00;
-        
-
-        tail_rotor_node.reference_point = glm::vec3(0.35, 2.3, 10.4);
-        main_rotor_node.reference_point = glm::vec3(0.0, 0.0, 0.0);
-
-
-
-        // Basic usage of shader helper:
-        // The example code below returns a shader object, which contains the field `.program_id`.
-        // The snippet is not enough to do the assignment, and will need to be modified (outside of
-        // just using the correct path), but it only needs to be called once
-        //
-        let shader: shader::Shader;
-        unsafe {
-            shader = shader::ShaderBuilder::new()
-            .attach_file("./shaders/simple.vert")   
-            .attach_file("./shaders/simple.frag")
-            .link();
-            shader.activate();
+        // The window is sized in logical pixels, so it is scaled up on HiDPI screens. Everything drawn to it goes by its
+        // physical size.
+        let mut window_size = context.window().inner_size();
+        println!("Window: {}x{} at scale factor {}", window_size.width, window_size.height, context.window().scale_factor());
+        unsafe { gl::Viewport(0, 0, window_size.width as i32, window_size.height as i32) };
+
+        let mut last_frame_time = std::time::Instant::now();
+        let mut elapsed = 0.0;
+
+        // With the default bindings, WASD moves the camera relative to where it looks, Q and E move it down and up,
+        // and the arrow keys turn it. G grabs and hides the cursor, which lets the mouse turn it as well, or pass
+        // --grab-cursor to start grabbed.
+        let mut camera = Camera::default();
+        if let Some(sensitivity) = util::flag_value(&args, "--mouse-sensitivity") {
+            camera.sensitivity = sensitivity;
+        }
+        if let Some(speed) = util::flag_value(&args, "--camera-speed") {
+            camera.speed = speed;
+        }
+        let mut cursor_grabbed = false;
+        if args.iter().any(|arg| arg == "--grab-cursor") {
+            cursor_grabbed = set_cursor_grab(context.window(), true);
         }
 
-        // Used to demonstrate keyboard handling -- feel free to remove
-        let mut _arbitrary_number = 0.0;
-
-        let first_frame_time = std::time::Instant::now();
-        let mut last_frame_time = first_frame_time;
+        // V switches between flying freely, orbiting the middle of the helicopter's path, and chasing the helicopter.
+        // When orbiting, the mouse turns around the target while the cursor is grabbed, WASD/QE move it and the scroll
+        // wheel zooms. When chasing, the scroll wheel moves closer or further away.
+        let mut camera_mode = CameraMode::FreeFly;
+        let mut orbit_camera = OrbitCamera { target: glm::vec3(0.0, 0.0, -40.0), ..Default::default() };
+        let mut chase_camera = ChaseCamera::default();
+
+        // H or the gamepad's start button takes over flying the helicopter from its autopilot, or hands it back.
+        // The collective climbs and descends, the cyclic tilts it the way it should fly, and the pedals turn it.
+        // Pass --fly to start out flying.
+        let mut flight = if args.iter().any(|arg| arg == "--fly") {
+            scene.animate(0.0);
+            Some(Flight::from_node(&scene.body_node))
+        } else {
+            None
+        };
 
-        // Variables to store the motion
-        let mut movement_coords = glm::vec3(0.0, 0.0, 0.0);
-        let mut rotation_coords =  glm::vec3(0.0, 0.0, 0.0);
-        let speed_constant: f32 = 100.0;
+        // Every camera starts out with the perspective set by --fov, --near, --far (which may be inf) and --reverse-z.
+        // P switches the current camera between that and an orthographic projection, while T and I orbit in an
+        // orthographic top-down or isometric view.
+        let default_perspective = Perspective::default();
+        let perspective = Perspective {
+            fov: util::flag_value(&args, "--fov").unwrap_or(default_perspective.fov),
+            near: util::flag_value(&args, "--near").unwrap_or(default_perspective.near),
+            far: util::flag_value(&args, "--far").unwrap_or(default_perspective.far),
+            reverse_z: args.iter().any(|arg| arg == "--reverse-z"),
+        };
+        camera.projection = Projection::Perspective(perspective);
+        orbit_camera.projection = Projection::Perspective(perspective);
+        chase_camera.projection = Projection::Perspective(perspective);
 
         // The main rendering loop
         loop {
             let now = std::time::Instant::now();
-            let elapsed = now.duration_since(first_frame_time).as_secs_f32();
-            let delta_time = now.duration_since(last_frame_time).as_secs_f32();
+            // While recording, time moves on by the same amount every frame however long the frame took
+            let delta_time = match &recording {
+                Some(recording) => recording.timestep,
+                None => now.duration_since(last_frame_time).as_secs_f32(),
+            };
+            elapsed += delta_time;
             last_frame_time = now;
 
-            // Handle keyboard input
-            if let Ok(keys) = pressed_keys.lock() {
-                for key in keys.iter() {
-                    match key {
-                        VirtualKeyCode::D => {
-                            movement_coords[0] += delta_time * speed_constant;
-                        },
-                        VirtualKeyCode::A => {
-                            movement_coords[0] -= delta_time * speed_constant;
-                        },
-                        VirtualKeyCode::W => {
-                            movement_coords[1] += delta_time * speed_constant;
-                        },
-                        VirtualKeyCode::S => {
-                            movement_coords[1] -= delta_time * speed_constant;
-                        },
-                        VirtualKeyCode::Q => {
-                            movement_coords[2] += delta_time * speed_constant;
-                        },
-                        VirtualKeyCode::E => {
-                            movement_coords[2] -= delta_time * speed_constant;
-                        },
-                        VirtualKeyCode::Left => {
-                            rotation_coords[0] += delta_time
-                        },
-                        VirtualKeyCode::Right => {
-                            rotation_coords[0] -= delta_time
-                        },
-                        VirtualKeyCode::Up => {
-                            rotation_coords[1] += delta_time
-                        },
-                        VirtualKeyCode::Down => {
-                            rotation_coords[1] -= delta_time
-                        },
-                        _ => { }
-                    }
+            // Follow the window's size. A minimized window has no size, so the last one is kept until it comes back.
+            if let Some(size) = resized.lock().ok().and_then(|mut resized| resized.take()) {
+                if size.width > 0 && size.height > 0 && size != window_size {
+                    context.resize(size);
+                    unsafe { gl::Viewport(0, 0, size.width as i32, size.height as i32) };
+                    window_size = size;
                 }
             }
-            // Handle mouse movement. delta contains the x and y movement of the mouse since last frame in pixels
-            if let Ok(mut delta) = mouse_delta.lock() {
-
-
-
-                *delta = (0.0, 0.0);
-            }
 
+            // Rebuild the shaders when their sources are edited
             unsafe {
-                //let matrix = glm::Mat4::from([
-                //     [1.0, 0.0, 0.0, elapsed.sin()],
-                //     [0.0, 1.0, 0.0, elapsed.sin()],
-                //     [1.0, 0.0, 1.0, 0],
-                //     [0.0, 0.0, 0.0, 1.0],
-                // ]);
-                //Identity matrix
-                let identity_matrix: glm::Mat4 = glm::identity();
-
-                // Projection
-                let projection: glm::Mat4 = glm::perspective(SCREEN_H as f32 / SCREEN_W as f32, 0.5, 1.0, 1000.0);
+                match scene.renderer.shaders.reload_if_changed() {
+                    Some(Ok(())) => println!("Reloaded shaders."),
+                    Some(Err(e)) => println!("{}", e),
+                    None => { },
+                }
+            }
 
-                //View
-                let movement: glm::Mat4 = glm::translation(&movement_coords);
-                let rotation: glm::Mat4 = glm::rotation(-rotation_coords[1], &glm::vec3(1.0, 0.0, 0.0)) * glm::rotation(rotation_coords[0], &glm::vec3(0.0, 1.0, 0.0));
-                let combination: glm::Mat4 = movement * rotation;
+            // Take in the input since last frame
+            input.update();
+            let direction = glm::vec3(input.value(Action::MoveRight), input.value(Action::MoveUp), input.value(Action::MoveForward));
+            let turn = glm::vec2(input.value(Action::TurnRight), input.value(Action::TurnUp));
+            // Mouse movement since last frame in pixels, and scroll wheel steps. The mouse only looks around while the
+            // cursor is grabbed, or moving it over other windows would turn the camera.
+            let look = if cursor_grabbed {
+                (input.value(Action::LookX), input.value(Action::LookY))
+            } else {
+                (0.0, 0.0)
+            };
+            let scroll = input.value(Action::Zoom);
+
+            if input.pressed(Action::CycleCamera) {
+                camera_mode = camera_mode.next();
+                println!("Camera: {:?}", camera_mode);
+            }
 
-                //Model
-                let model: glm::Mat4 = glm::translation(&glm::vec3(0.0, 0.0, -4.0));
+            if input.pressed(Action::ToggleProjection) {
+                // The orthographic view shows about as much as the perspective one does around what is looked at
+                let (projection, distance) = match camera_mode {
+                    CameraMode::FreeFly => (&mut camera.projection, 50.0),
+                    CameraMode::Orbit => (&mut orbit_camera.projection, orbit_camera.distance),
+                    CameraMode::Chase => (&mut chase_camera.projection, chase_camera.offset.norm()),
+                };
+                *projection = match projection {
+                    Projection::Perspective(_) => perspective.matching_orthographic(distance),
+                    Projection::Orthographic { .. } => Projection::Perspective(perspective),
+                };
+                println!("Projection: {:?}", projection);
+            }
 
-                let matrix: glm::Mat4 = (projection * identity_matrix) * (model * identity_matrix) * (movement * rotation * identity_matrix); 
+            let preset = if input.pressed(Action::TopDown) {
+                Some(ViewPreset::TopDown)
+            } else if input.pressed(Action::Isometric) {
+                Some(ViewPreset::Isometric)
+            } else {
+                None
+            };
+            if let Some(preset) = preset {
+                camera_mode = CameraMode::Orbit;
+                orbit_camera.set_preset(preset, &perspective);
+                println!("Camera: {:?} {:?}", camera_mode, preset);
+            }
 
-                let transformation_loc = shader.get_uniform_location("transformation");
-               // gl::UniformMatrix4fv(transformation_loc, 1, gl::FALSE, matrix.as_ptr());
+            match camera_mode {
+                CameraMode::FreeFly => {
+                    camera.turn(turn.x * camera.turn_speed * delta_time, turn.y * camera.turn_speed * delta_time);
+                    camera.fly(&direction, delta_time);
+                    camera.look(look);
+                },
+                CameraMode::Orbit => {
+                    orbit_camera.look(look);
+                    orbit_camera.zoom(scroll);
+                    orbit_camera.pan(&direction, delta_time);
+                },
+                CameraMode::Chase => {
+                    chase_camera.zoom(scroll);
+                },
+            }
 
-                gl::ClearColor(0.2, 0.3, 0.3, 1.0); // moon raker, full opacity
-                gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+            if input.pressed(Action::GrabCursor) {
+                cursor_grabbed = set_cursor_grab(context.window(), !cursor_grabbed);
+            }
 
-                gl::Enable(gl::BLEND);
-                gl::Disable(gl::CULL_FACE); 
-                gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            // Blast a crater into the terrain below the helicopter when C is pressed
+            if input.pressed(Action::Crater) {
+                if let Some(terrain) = &mut scene.dynamic_terrain {
+                    let center = scene.body_node.position;
+                    terrain.modify(|mesh| mesh.add_crater(&center, 10.0, 3.0));
+                }
+            }
 
-                unsafe fn draw_scene(node: &scene_graph::SceneNode, view_projection_matrix: &glm::Mat4) {
-                    if node.index_count > 0 {  
-                        gl::UniformMatrix4fv(2, 1, 0, (view_projection_matrix*node.current_transformation_matrix).as_ptr());
-                        gl::BindVertexArray(node.vao_id);
-                        gl::DrawElements(gl::TRIANGLES, node.index_count, gl::UNSIGNED_INT, ptr::null());
-                    }
-                    for &child in &node.children {
-                        draw_scene(&*child, view_projection_matrix);
+            // M colors the terrain by each of the color modes in turn, skipping those it can't be colored by
+            if input.pressed(Action::ColorMode) {
+                for _ in ColorMode::NAMES {
+                    let i = color_mode.map_or(0, |i| (i + 1) % ColorMode::NAMES.len());
+                    color_mode = Some(i);
+                    if scene.color_terrain(&ColorMode::from_name(ColorMode::NAMES[i]).unwrap()).is_ok() {
+                        println!("Terrain colored by {}", ColorMode::NAMES[i]);
+                        break;
                     }
                 }
+            }
 
-                unsafe fn update_node_transformations(node: &mut scene_graph::SceneNode, transformation_so_far: &glm::Mat4) {
-                    //Construct the correct transformation matrix
-                    let mut transformation = *transformation_so_far; //The first transformation_so_far will be an identity matrix
+            if input.pressed(Action::Lighting) {
+                lighting = (lighting + 1) % LightUniforms::PRESET_NAMES.len();
+                let name = LightUniforms::PRESET_NAMES[lighting];
+                unsafe { scene.renderer.light_uniforms.update(&LightUniforms::preset(name).unwrap()) };
+                println!("Lighting: {}", name);
+            }
 
-                    transformation = glm::translation(&glm::vec3(-node.reference_point.x, -node.reference_point.y, -node.reference_point.z))*transformation;
+            let take_screenshot = input.pressed(Action::Screenshot);
 
-                    transformation = glm::rotation(node.rotation.x, &glm::vec3(1.0, 0.0, 0.0))*transformation;
-                    transformation = glm::rotation(node.rotation.y, &glm::vec3(0.0, 1.0, 0.0))*transformation;
-                    transformation = glm::rotation(node.rotation.z, &glm::vec3(0.0, 0.0, 1.0))*transformation;
+            if input.pressed(Action::Record) {
+                recording = match recording {
+                    Some(recording) => {
+                        println!("Stopped recording to {}", recording.dir.display());
+                        None
+                    },
+                    None => {
+                        let recording = capture::Recording::new(std::path::Path::new("recordings"), record_fps);
+                        println!("Recording to {}", recording.dir.display());
+                        Some(recording)
+                    },
+                };
+            }
 
-                    transformation = glm::translation(&glm::vec3(node.reference_point.x, node.reference_point.y, node.reference_point.z))*transformation;
+            if input.pressed(Action::ToggleFlight) {
+                flight = match flight {
+                    Some(_) => None,
+                    None => Some(Flight::from_node(&scene.body_node)),
+                };
+                println!("{}", if flight.is_some() { "Flying by hand" } else { "Autopilot" });
+            }
 
-                    transformation = glm::translation(&glm::vec3(node.position.x, node.position.y, node.position.z))*transformation;
+            // Fly the helicopter around its circuit, as the headless renderer poses it
+            scene.animate(elapsed);
+            if let Some(flight) = &mut flight {
+                let controls = FlightControls {
+                    collective: input.value(Action::Collective),
+                    cyclic: glm::vec2(input.value(Action::CyclicX), input.value(Action::CyclicY)),
+                    pedals: input.value(Action::Pedals),
+                };
+                let ground = scene.terrain_height(flight.position.x, flight.position.z);
+                flight.update(&controls, ground, delta_time);
+                flight.apply(&mut scene.body_node);
+            }
+            // Follows the helicopter's transform from the last frame, as this frame's is worked out while rendering
+            chase_camera.update(&scene.body_node, |x, z| scene.terrain_height(x, z), delta_time);
 
-                    //Update the node's transformation matrix
-                    node.current_transformation_matrix = transformation;
+            unsafe {
+                //let matrix = glm::Mat4::from([
+                //     [1.0, 0.0, 0.0, elapsed.sin()],
+                //     [0.0, 1.0, 0.0, elapsed.sin()],
+                //     [1.0, 0.0, 1.0, 0],
+                //     [0.0, 0.0, 0.0, 1.0],
+                // ]);
 
-                    for &child in &node.children {
-                        update_node_transformations(&mut *child, &node.current_transformation_matrix);
-                    }
+                //View and projection, from whichever camera is in use
+                let (view, camera_position, projection) = match camera_mode {
+                    CameraMode::FreeFly => (camera.view(), camera.position, camera.projection),
+                    CameraMode::Orbit => (orbit_camera.view(), orbit_camera.position(), orbit_camera.projection),
+                    CameraMode::Chase => (chase_camera.view(), chase_camera.position(), chase_camera.projection),
+                };
+                let aspect = window_size.width as f32 / window_size.height as f32;
+
+                scene.render(&View {
+                    view,
+                    projection: projection.matrix(aspect),
+                    camera_position,
+                    reverse_z: projection.reverse_z(),
+                }, elapsed);
+
+                // Read back the frame before it is swapped out
+                if take_screenshot {
+                    let path = std::path::PathBuf::from(format!("screenshots/screenshot_{}.png", capture::timestamp()));
+                    frame_capture.capture(&path, window_size.width, window_size.height);
                 }
-
-                update_node_transformations(&mut root_node , &identity_matrix);
-
-                draw_scene(&root_node, &projection);
-
-
-                // // Issue the necessary commands to draw your scene here
-                // gl::BindVertexArray(vao_terrain);
-                // gl::DrawElements(gl::TRIANGLES, terrain.index_count, gl::UNSIGNED_INT, ptr::null());
-                // gl::BindVertexArray(vao_body);
-                // gl::DrawElements(gl::TRIANGLES, helicopter.body.index_count, gl::UNSIGNED_INT, ptr::null());
-                // gl::BindVertexArray(vao_door);
-                // gl::DrawElements(gl::TRIANGLES,  helicopter.door.index_count, gl::UNSIGNED_INT, ptr::null());
-                // gl::BindVertexArray(vao_main_rotor);
-                // gl::DrawElements(gl::TRIANGLES,  helicopter.main_rotor.index_count, gl::UNSIGNED_INT, ptr::null());
-                // gl::BindVertexArray(vao_tail_rotor);
-                // gl::DrawElements(gl::TRIANGLES,  helicopter.tail_rotor.index_count, gl::UNSIGNED_INT, ptr::null());
-
-                    
-
+                if let Some(recording) = &mut recording {
+                    frame_capture.capture(&recording.next_path(), window_size.width, window_size.height);
+                }
+                frame_capture.poll();
             }
 
             context.swap_buffers().unwrap();
@@ -429,7 +417,7 @@ fn main() {
     let render_thread_healthy = Arc::new(RwLock::new(true));
     let render_thread_watchdog = Arc::clone(&render_thread_healthy);
     thread::spawn(move || {
-        if !render_thread.join().is_ok() {
+        if render_thread.join().is_err() {
             if let Ok(mut health) = render_thread_watchdog.write() {
                 println!("Render thread panicked!");
                 *health = false;
@@ -443,7 +431,7 @@ fn main() {
 
         // Terminate program if render thread panics
         if let Ok(health) = render_thread_healthy.read() {
-            if *health == false {
+            if !*health {
                 *control_flow = ControlFlow::Exit;
             }
         }
@@ -452,43 +440,50 @@ fn main() {
             Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => {
                 *control_flow = ControlFlow::Exit;
             },
-            // Keep track of currently pressed keys to send to the rendering thread
+            // Send input on to the rendering thread. Sending fails only once it has stopped, so errors are ignored.
             Event::WindowEvent { event: WindowEvent::KeyboardInput {
                 input: KeyboardInput { state: key_state, virtual_keycode: Some(keycode), .. }, .. }, .. } => {
 
-                if let Ok(mut keys) = arc_pressed_keys.lock() {
-                    match key_state {
-                        Released => {
-                            if keys.contains(&keycode) {
-                                let i = keys.iter().position(|&k| k == keycode).unwrap();
-                                keys.remove(i);
-                            }
-                        },
-                        Pressed => {
-                            if !keys.contains(&keycode) {
-                                keys.push(keycode);
-                            }
-                        }
-                    }
-                }
+                let button = Button::Key(keycode);
+                let _ = input_events.send(match key_state {
+                    Pressed => InputEvent::Pressed(button),
+                    Released => InputEvent::Released(button),
+                });
 
-                // Handle escape separately
-                match keycode {
-                    Escape => {
-                        *control_flow = ControlFlow::Exit;
-                    },
-                    // Q => {
-                    //     *control_flow = ControlFlow::Exit;
-                    // }
-                    _ => { }
+                // Quitting is handled here, as only the event loop can end itself
+                if key_state == Pressed && bindings.is_bound(Action::Quit, button) {
+                    *control_flow = ControlFlow::Exit;
+                }
+            },
+            Event::WindowEvent { event: WindowEvent::MouseInput { state, button, .. }, .. } => {
+                let button = Button::Mouse(button);
+                let _ = input_events.send(match state {
+                    Pressed => InputEvent::Pressed(button),
+                    Released => InputEvent::Released(button),
+                });
+                if state == Pressed && bindings.is_bound(Action::Quit, button) {
+                    *control_flow = ControlFlow::Exit;
                 }
             },
             Event::DeviceEvent { event: DeviceEvent::MouseMotion { delta }, .. } => {
-                // Accumulate mouse movement
-                if let Ok(mut position) = arc_mouse_delta.lock() {
-                    *position = (position.0 + delta.0 as f32, position.1 + delta.1 as f32);
+                let _ = input_events.send(InputEvent::Moved(Axis::MouseX, delta.0 as f32));
+                let _ = input_events.send(InputEvent::Moved(Axis::MouseY, delta.1 as f32));
+            },
+            // Let the rendering thread know about the new size, which also changes when the window moves to a screen
+            // with another scale factor
+            Event::WindowEvent { event: WindowEvent::Resized(size), .. } => {
+                if let Ok(mut resized) = arc_resized.lock() {
+                    *resized = Some(size);
                 }
             },
+            Event::WindowEvent { event: WindowEvent::ScaleFactorChanged { new_inner_size, .. }, .. } => {
+                if let Ok(mut resized) = arc_resized.lock() {
+                    *resized = Some(*new_inner_size);
+                }
+            },
+            Event::WindowEvent { event: WindowEvent::MouseWheel { delta, .. }, .. } => {
+                let _ = input_events.send(InputEvent::scroll(delta));
+            },
             _ => { }
         }
     });
@@ -1,30 +1,226 @@
-use tobj;
+extern crate nalgebra_glm as glm;
+
+use crate::terrain::{Heightmap, NoiseParams, TerrainParams, ramp_color};
 
 // internal helper
 fn generate_color_vec(color: [f32; 4], num: usize) -> Vec<f32> {
     color.iter().cloned().cycle().take(num*4).collect()
 }
 
+// Scale a list of values into [0, 1]
+fn normalize_values(values: &[f32]) -> Vec<f32> {
+    let min = values.iter().cloned().fold(f32::INFINITY, f32::min);
+    let max = values.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+    let range = if max > min { max - min } else { 1.0 };
+    values.iter().map(|v| (v - min) / range).collect()
+}
+
 pub struct Mesh {
     pub vertices: Vec<f32>,
     pub normals: Vec<f32>,
+    pub uvs: Vec<f32>,
     pub colors: Vec<f32>,
     pub indices: Vec<u32>,
     pub index_count: i32,
 }
 
+// Ways of coloring a mesh per vertex. The ramps map a value in [0, 1] to a color, see `terrain::ramp_color`
+#[derive(Clone)]
+pub enum ColorMode {
+    Constant([f32; 4]),
+    Height(Vec<(f32, [f32; 4])>),            // By elevation, lowest vertex at 0 and highest at 1
+    Slope(Vec<(f32, [f32; 4])>),             // By steepness, flat at 0 and vertical at 1
+    Normal,                                  // The normal direction mapped to RGB
+    Curvature(Vec<(f32, [f32; 4])>),         // Valleys towards 0, flat at 0.5 and ridges towards 1
+    ScalarField(Vec<f32>, Vec<(f32, [f32; 4])>), // One value per vertex, scaled to [0, 1]
+}
+
+impl ColorMode {
+    // The modes that can be picked by name, e.g. with --color-mode, in the order they are cycled through
+    pub const NAMES: &'static [&'static str] = &["plain", "height", "slope", "normal", "curvature"];
+
+    // A mode by name, with a ramp that shows it off
+    pub fn from_name(name: &str) -> Option<ColorMode> {
+        let mode = match name {
+            "plain" => ColorMode::Constant([1.0, 1.0, 1.0, 1.0]),
+            "height" => ColorMode::Height(vec![
+                (0.0, [0.20, 0.30, 0.60, 1.0]),
+                (0.3, [0.30, 0.60, 0.30, 1.0]),
+                (0.7, [0.55, 0.45, 0.30, 1.0]),
+                (1.0, [0.95, 0.95, 0.95, 1.0]),
+            ]),
+            "slope" => ColorMode::Slope(vec![
+                (0.0, [0.30, 0.70, 0.30, 1.0]),
+                (0.3, [0.90, 0.80, 0.30, 1.0]),
+                (0.6, [0.80, 0.30, 0.20, 1.0]),
+            ]),
+            "normal" => ColorMode::Normal,
+            "curvature" => ColorMode::Curvature(vec![
+                (0.0, [0.20, 0.30, 0.90, 1.0]),
+                (0.5, [0.80, 0.80, 0.80, 1.0]),
+                (1.0, [0.90, 0.50, 0.10, 1.0]),
+            ]),
+            _ => return None,
+        };
+        Some(mode)
+    }
+}
+
 impl Mesh {
     pub fn from(mesh: tobj::Mesh, color: [f32; 4]) -> Self {
         let num_verts = mesh.positions.len() / 3;
         let index_count = mesh.indices.len() as i32;
+        // Keep the vertex colors of the model if it has any, they come without alpha
+        let colors = if mesh.vertex_color.len() == num_verts * 3 {
+            mesh.vertex_color.chunks(3).flat_map(|c| [c[0], c[1], c[2], color[3]]).collect()
+        } else {
+            generate_color_vec(color, num_verts)
+        };
         Mesh {
             vertices: mesh.positions,
             normals: mesh.normals,
+            uvs: mesh.texcoords,
             indices: mesh.indices,
-            colors: generate_color_vec(color, num_verts),
+            colors,
             index_count,
         }
     }
+
+    pub fn vertex_count(&self) -> usize {
+        self.vertices.len() / 3
+    }
+
+    // Replace the vertex colors. Upload them with DynamicMesh::modify to see the change
+    pub fn recolor(&mut self, mode: &ColorMode) {
+        let n = self.vertex_count();
+        self.colors = match mode {
+            ColorMode::Constant(color) => generate_color_vec(*color, n),
+            ColorMode::Height(ramp) => {
+                let heights: Vec<f32> = self.vertices.chunks(3).map(|v| v[1]).collect();
+                return self.recolor(&ColorMode::ScalarField(heights, ramp.clone()));
+            },
+            ColorMode::Slope(ramp) => {
+                assert_eq!(self.normals.len(), 3 * n, "Coloring by slope needs one normal per vertex");
+                let slopes: Vec<f32> = self.normals.chunks(3)
+                    .map(|n| n[1].clamp(-1.0, 1.0).acos() / std::f32::consts::FRAC_PI_2)
+                    .collect();
+                self.colors_from_values(&slopes, ramp)
+            },
+            ColorMode::Normal => {
+                assert_eq!(self.normals.len(), 3 * n, "Coloring by normal needs one normal per vertex");
+                self.normals.chunks(3)
+                    .flat_map(|n| [n[0] * 0.5 + 0.5, n[1] * 0.5 + 0.5, n[2] * 0.5 + 0.5, 1.0])
+                    .collect()
+            },
+            ColorMode::Curvature(ramp) => {
+                let curvature = self.curvature();
+                let max = curvature.iter().fold(0.0f32, |max, c| max.max(c.abs()));
+                let scale = if max > 0.0 { 0.5 / max } else { 0.0 };
+                let values: Vec<f32> = curvature.iter().map(|c| 0.5 + c * scale).collect();
+                self.colors_from_values(&values, ramp)
+            },
+            ColorMode::ScalarField(values, ramp) => {
+                assert_eq!(values.len(), n, "The scalar field needs one value per vertex");
+                self.colors_from_values(&normalize_values(values), ramp)
+            },
+        };
+    }
+
+    fn colors_from_values(&self, values: &[f32], ramp: &[(f32, [f32; 4])]) -> Vec<f32> {
+        values.iter().flat_map(|&v| ramp_color(ramp, v)).collect()
+    }
+
+    // Approximate curvature per vertex: how far a vertex sits above (positive) or below (negative)
+    // the average of its neighbours along its normal, relative to the length of its edges
+    pub fn curvature(&self) -> Vec<f32> {
+        let n = self.vertex_count();
+        let mut neighbour_sum = vec![glm::vec3(0.0, 0.0, 0.0); n];
+        let mut edge_length_sum = vec![0.0f32; n];
+        let mut neighbour_count = vec![0u32; n];
+        for triangle in self.indices.chunks(3) {
+            for k in 0..3 {
+                let (a, b) = (triangle[k] as usize, triangle[(k + 1) % 3] as usize);
+                let edge_length = glm::distance(&self.position(a), &self.position(b));
+                neighbour_sum[a] += self.position(b);
+                neighbour_sum[b] += self.position(a);
+                edge_length_sum[a] += edge_length;
+                edge_length_sum[b] += edge_length;
+                neighbour_count[a] += 1;
+                neighbour_count[b] += 1;
+            }
+        }
+
+        (0..n).map(|i| {
+            if neighbour_count[i] == 0 || edge_length_sum[i] == 0.0 || self.normals.len() < 3 * (i + 1) {
+                return 0.0;
+            }
+            let count = neighbour_count[i] as f32;
+            let offset = self.position(i) - neighbour_sum[i] / count;
+            let normal = glm::vec3(self.normals[3*i], self.normals[3*i + 1], self.normals[3*i + 2]);
+            glm::dot(&offset, &normal) / (edge_length_sum[i] / count)
+        }).collect()
+    }
+
+    // Press a bowl shaped crater into the mesh, lowering vertices within `radius` of `center` in the
+    // xz-plane by up to `depth`, and recompute the normals of every vertex next to one that moved.
+    // Returns the range of vertices that changed, for DynamicMesh::modify.
+    pub fn add_crater(&mut self, center: &glm::Vec3, radius: f32, depth: f32) -> Option<std::ops::Range<usize>> {
+        let n = self.vertex_count();
+        let mut changed = vec![false; n];
+        for (i, changed) in changed.iter_mut().enumerate() {
+            let distance = glm::distance(&glm::vec2(self.vertices[3*i], self.vertices[3*i + 2]), &glm::vec2(center.x, center.z));
+            if distance < radius {
+                let t = distance / radius;
+                self.vertices[3*i + 1] -= depth * (1.0 - t * t);
+                *changed = true;
+            }
+        }
+        if !changed.contains(&true) {
+            return None;
+        }
+
+        // Triangles touching a moved vertex change shape, which changes the normals of all their corners
+        if self.normals.len() == 3 * n {
+            let mut ring = changed.clone();
+            for triangle in self.indices.chunks(3) {
+                if triangle.iter().any(|&i| changed[i as usize]) {
+                    for &i in triangle {
+                        ring[i as usize] = true;
+                    }
+                }
+            }
+            changed = ring;
+
+            // Sum up the area weighted normals of every triangle touching one of those corners
+            let mut normal_sum = vec![glm::vec3(0.0, 0.0, 0.0); n];
+            for triangle in self.indices.chunks(3) {
+                if !triangle.iter().any(|&i| changed[i as usize]) {
+                    continue;
+                }
+                let (a, b, c) = (triangle[0] as usize, triangle[1] as usize, triangle[2] as usize);
+                let normal = glm::cross(&(self.position(b) - self.position(a)), &(self.position(c) - self.position(a)));
+                for &i in triangle {
+                    normal_sum[i as usize] += normal;
+                }
+            }
+            for i in (0..n).filter(|&i| changed[i] && normal_sum[i].norm() > 0.0) {
+                let old = glm::vec3(self.normals[3*i], self.normals[3*i + 1], self.normals[3*i + 2]);
+                let mut normal = normal_sum[i].normalize();
+                // Keep the side the normals were on, whichever way the triangles are wound
+                if glm::dot(&normal, &old) < 0.0 {
+                    normal = -normal;
+                }
+                self.normals[3*i..3*i + 3].copy_from_slice(normal.as_slice());
+            }
+        }
+        let first = changed.iter().position(|&c| c)?;
+        let last = changed.iter().rposition(|&c| c)?;
+        Some(first..last + 1)
+    }
+
+    fn position(&self, i: usize) -> glm::Vec3 {
+        glm::vec3(self.vertices[3*i], self.vertices[3*i + 1], self.vertices[3*i + 2])
+    }
 }
 
 pub struct Terrain;
@@ -43,7 +239,7 @@ impl Terrain {
         let after = std::time::Instant::now();
         println!("Done in {:.3}ms.", after.duration_since(before).as_micros() as f32 / 1e3);
 
-        if models.len() > 1 || models.len() == 0 {
+        if models.len() > 1 || models.is_empty() {
             panic!("Please use a model with a single mesh!")
             // You could try merging the vertices and indices
             // of the separate meshes into a single mesh.
@@ -59,6 +255,112 @@ impl Terrain {
 
         Mesh::from(terrain.mesh, [1.0, 1.0, 1.0, 1.0])
     }
+
+    // Build a terrain from a grayscale heightmap image
+    #[allow(dead_code)]
+    pub fn from_heightmap(path: &str, params: &TerrainParams) -> Mesh {
+        println!("Generating terrain from heightmap...");
+        let before = std::time::Instant::now();
+        let heightmap = Heightmap::from_image(path);
+        let terrain = heightmap.to_mesh(params);
+        let after = std::time::Instant::now();
+        println!("Done in {:.3}ms.", after.duration_since(before).as_micros() as f32 / 1e3);
+        println!("Generated {}x{} terrain with {} points and {} triangles.",
+            heightmap.width,
+            heightmap.depth,
+            terrain.vertices.len() / 3,
+            terrain.indices.len() / 3,
+        );
+        terrain
+    }
+
+    // Build a width x depth terrain from a procedural noise function
+    #[allow(dead_code)]
+    pub fn from_noise(width: usize, depth: usize, noise: &NoiseParams, params: &TerrainParams) -> Mesh {
+        println!("Generating terrain from noise...");
+        let before = std::time::Instant::now();
+        let terrain = Heightmap::from_noise(width, depth, noise).to_mesh(params);
+        let after = std::time::Instant::now();
+        println!("Done in {:.3}ms.", after.duration_since(before).as_micros() as f32 / 1e3);
+        println!("Generated {}x{} terrain with {} points and {} triangles.",
+            width,
+            depth,
+            terrain.vertices.len() / 3,
+            terrain.indices.len() / 3,
+        );
+        terrain
+    }
+}
+
+// Simple shapes centered on the origin, with counter-clockwise front faces
+pub struct Primitive;
+#[allow(dead_code)]
+impl Primitive {
+    // A flat square in the xz-plane, facing up
+    pub fn plane(size: f32, color: [f32; 4]) -> Mesh {
+        let h = size / 2.0;
+        Mesh {
+            vertices: vec![-h, 0.0, -h,  -h, 0.0, h,  h, 0.0, h,  h, 0.0, -h],
+            normals: [0.0, 1.0, 0.0].repeat(4),
+            uvs: vec![0.0, 0.0,  0.0, 1.0,  1.0, 1.0,  1.0, 0.0],
+            colors: generate_color_vec(color, 4),
+            indices: vec![0, 1, 2,  0, 2, 3],
+            index_count: 6,
+        }
+    }
+
+    // A cube with flat shaded sides, four vertices each
+    pub fn cube(size: f32, color: [f32; 4]) -> Mesh {
+        let h = size / 2.0;
+        let mut mesh = Mesh { vertices: vec![], normals: vec![], uvs: vec![], colors: vec![], indices: vec![], index_count: 0 };
+        for axis in 0..3 {
+            for &sign in &[1.0f32, -1.0] {
+                let mut normal = glm::vec3(0.0, 0.0, 0.0);
+                normal[axis] = sign;
+                // Two directions spanning the side, ordered so that u x v points along the normal
+                let mut u = glm::vec3(0.0, 0.0, 0.0);
+                u[(axis + 1) % 3] = 1.0;
+                let v = glm::cross(&normal, &u);
+
+                let first = mesh.vertex_count() as u32;
+                for &(s, t) in &[(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+                    let position = (normal + u * s + v * t) * h;
+                    mesh.vertices.extend_from_slice(position.as_slice());
+                    mesh.normals.extend_from_slice(normal.as_slice());
+                    mesh.uvs.extend_from_slice(&[(s + 1.0) / 2.0, (t + 1.0) / 2.0]);
+                }
+                mesh.indices.extend_from_slice(&[first, first + 1, first + 2, first, first + 2, first + 3]);
+            }
+        }
+        mesh.colors = generate_color_vec(color, mesh.vertex_count());
+        mesh.index_count = mesh.indices.len() as i32;
+        mesh
+    }
+
+    // A UV sphere with `segments` slices around the y-axis and `rings` stacks from pole to pole
+    pub fn sphere(radius: f32, segments: u32, rings: u32, color: [f32; 4]) -> Mesh {
+        let mut mesh = Mesh { vertices: vec![], normals: vec![], uvs: vec![], colors: vec![], indices: vec![], index_count: 0 };
+        for ring in 0..=rings {
+            let theta = std::f32::consts::PI * ring as f32 / rings as f32;
+            for segment in 0..=segments {
+                let phi = 2.0 * std::f32::consts::PI * segment as f32 / segments as f32;
+                let normal = glm::vec3(theta.sin() * phi.cos(), theta.cos(), -theta.sin() * phi.sin());
+                mesh.vertices.extend_from_slice((normal * radius).as_slice());
+                mesh.normals.extend_from_slice(normal.as_slice());
+                mesh.uvs.extend_from_slice(&[segment as f32 / segments as f32, ring as f32 / rings as f32]);
+            }
+        }
+        let row = segments + 1;
+        for ring in 0..rings {
+            for segment in 0..segments {
+                let (a, b) = (ring * row + segment, (ring + 1) * row + segment);
+                mesh.indices.extend_from_slice(&[a, b, b + 1, a, b + 1, a + 1]);
+            }
+        }
+        mesh.colors = generate_color_vec(color, mesh.vertex_count());
+        mesh.index_count = mesh.indices.len() as i32;
+        mesh
+    }
 }
 
 use std::ops::Index;
@@ -72,7 +374,7 @@ pub struct Helicopter {
 // You can use square brackets to access the components of the helicopter, if you want to use loops!
 impl Index<usize> for Helicopter {
     type Output = Mesh;
-    fn index<'a>(&'a self, i: usize) -> &'a Mesh {
+    fn index(&self, i: usize) -> &Mesh {
         match i {
             0 => &self.body,
             1 => &self.main_rotor,
@@ -115,3 +417,79 @@ impl Helicopter {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Black at 0 and white at 1, so the red channel of a color is the value it was picked by
+    fn ramp() -> Vec<(f32, [f32; 4])> {
+        vec![(0.0, [0.0, 0.0, 0.0, 1.0]), (1.0, [1.0, 1.0, 1.0, 1.0])]
+    }
+
+    fn reds(mesh: &Mesh) -> Vec<f32> {
+        mesh.colors.chunks(4).map(|c| c[0]).collect()
+    }
+
+    #[test]
+    fn recolor_by_height_spans_the_ramp() {
+        // Heights of 0, 10 and 20 along the x-axis
+        let mut mesh = Heightmap::from_fn(3, 2, |x, _| x as f32 * 0.5).to_mesh(&TerrainParams::default());
+        mesh.recolor(&ColorMode::Height(ramp()));
+        assert_eq!(reds(&mesh), vec![0.0, 0.5, 1.0, 0.0, 0.5, 1.0]);
+        assert_eq!(mesh.colors.len(), mesh.vertex_count() * 4);
+    }
+
+    #[test]
+    fn recolor_by_slope_goes_from_flat_to_vertical() {
+        // Facing up, tilted 45 degrees and facing sideways
+        let tilted = std::f32::consts::FRAC_1_SQRT_2;
+        let mut mesh = Mesh {
+            vertices: vec![0.0; 9],
+            normals: vec![0.0, 1.0, 0.0,  tilted, tilted, 0.0,  1.0, 0.0, 0.0],
+            uvs: vec![],
+            colors: vec![],
+            indices: vec![0, 1, 2],
+            index_count: 3,
+        };
+        mesh.recolor(&ColorMode::Slope(ramp()));
+        for (red, expected) in reds(&mesh).into_iter().zip([0.0, 0.5, 1.0]) {
+            assert!((red - expected).abs() < 1e-6, "{} should be {}", red, expected);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "one normal per vertex")]
+    fn recolor_by_slope_needs_normals() {
+        let mut mesh = Heightmap::from_fn(3, 2, |_, _| 0.0).to_mesh(&TerrainParams::default());
+        mesh.normals.clear();
+        mesh.recolor(&ColorMode::Slope(ramp()));
+    }
+
+    #[test]
+    fn crater_recomputes_normals_around_it() {
+        let mut mesh = Heightmap::from_fn(9, 9, |_, _| 0.0).to_mesh(&TerrainParams::default());
+        let range = mesh.add_crater(&glm::vec3(0.0, 0.0, 0.0), 1.5, 1.0).unwrap();
+
+        // Every normal should be what the triangles around its vertex now give, starting from scratch
+        let mut expected = vec![glm::vec3(0.0, 0.0, 0.0); mesh.vertex_count()];
+        for triangle in mesh.indices.chunks(3) {
+            let (a, b, c) = (triangle[0] as usize, triangle[1] as usize, triangle[2] as usize);
+            let normal = glm::cross(&(mesh.position(b) - mesh.position(a)), &(mesh.position(c) - mesh.position(a)));
+            for &i in triangle {
+                expected[i as usize] += normal;
+            }
+        }
+        for (i, expected) in expected.iter().enumerate() {
+            let mut expected = expected.normalize();
+            if expected.y < 0.0 {
+                expected = -expected;
+            }
+            let normal = glm::vec3(mesh.normals[3*i], mesh.normals[3*i + 1], mesh.normals[3*i + 2]);
+            assert!(glm::distance(&normal, &expected) < 1e-5, "Vertex {}: {:?} should be {:?}", i, normal, expected);
+            if normal != glm::vec3(0.0, 1.0, 0.0) {
+                assert!(range.contains(&i), "Vertex {} changed outside {:?}", i, range);
+            }
+        }
+    }
+}
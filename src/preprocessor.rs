@@ -0,0 +1,204 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use crate::shader::ShaderError;
+
+// GLSL source with all includes resolved. Source string N in `#line` directives refers to files[N],
+// with files[0] being the file that was preprocessed.
+pub struct PreprocessedSource {
+    pub source : String,
+    pub files  : Vec<String>,
+}
+
+struct Preprocessor<'a> {
+    defines  : &'a [(String, String)],
+    read     : &'a dyn Fn(&Path) -> std::io::Result<String>,
+    files    : Vec<String>,
+    included : HashSet<PathBuf>,
+    output   : String,
+}
+
+// Resolve `#include "file.glsl"` relative to the including file, and inject `#define`s right after
+// `#version`. Every file is included at most once, which also breaks include cycles.
+pub fn preprocess(path: &str, defines: &[(String, String)]) -> Result<PreprocessedSource, ShaderError> {
+    preprocess_with(path, defines, &|path| std::fs::read_to_string(path))
+}
+
+// Like `preprocess`, but with the files read by `read`
+fn preprocess_with(path: &str, defines: &[(String, String)], read: &dyn Fn(&Path) -> std::io::Result<String>) -> Result<PreprocessedSource, ShaderError> {
+    let mut preprocessor = Preprocessor {
+        defines,
+        read,
+        files    : vec![],
+        included : HashSet::new(),
+        output   : String::new(),
+    };
+    let source = read(Path::new(path)).map_err(|e| ShaderError::Io { path: path.to_string(), message: e.to_string() })?;
+    let version_seen = preprocessor.expand(Path::new(path), &source)?;
+
+    let source = if version_seen {
+        preprocessor.output
+    } else {
+        format!("{}#line 1 0\n{}", preprocessor.define_block(), preprocessor.output)
+    };
+    Ok(PreprocessedSource { source, files: preprocessor.files })
+}
+
+// The file name in `#include "file.glsl"` or `#include <file.glsl>`
+fn parse_include_name(rest: &str) -> Option<&str> {
+    let rest = rest.trim();
+    let (open, close) = match rest.chars().next()? {
+        '"' => ('"', '"'),
+        '<' => ('<', '>'),
+        _ => return None,
+    };
+    let rest = rest.strip_prefix(open)?;
+    let end = rest.find(close)?;
+    Some(&rest[..end])
+}
+
+fn guard_key(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+impl<'a> Preprocessor<'a> {
+    fn define_block(&self) -> String {
+        self.defines.iter()
+            .map(|(name, value)| format!("#define {} {}\n", name, value))
+            .collect()
+    }
+
+    // Append the file's source to the output, returns whether a #version directive was found in it
+    fn expand(&mut self, path: &Path, source: &str) -> Result<bool, ShaderError> {
+        let index = self.files.len();
+        let name = path.to_string_lossy().to_string();
+        self.files.push(name.clone());
+        self.included.insert(guard_key(path));
+
+        let mut version_seen = false;
+        for (i, line) in source.lines().enumerate() {
+            let line_number = i + 1;
+            let trimmed = line.trim_start();
+
+            if index == 0 && !version_seen && trimmed.starts_with("#version") {
+                version_seen = true;
+                self.output.push_str(line);
+                self.output.push('\n');
+                let defines = self.define_block();
+                self.output.push_str(&defines);
+                self.output.push_str(&format!("#line {} {}\n", line_number + 1, index));
+                continue;
+            }
+
+            if let Some(rest) = trimmed.strip_prefix("#include") {
+                let include_name = parse_include_name(rest).ok_or_else(|| ShaderError::Preprocess {
+                    path: name.clone(),
+                    line: line_number as u32,
+                    message: format!("Malformed include: {}", trimmed),
+                })?;
+                let include_path = path.parent().unwrap_or_else(|| Path::new(".")).join(include_name);
+
+                if self.included.contains(&guard_key(&include_path)) {
+                    // Keep an empty line, so the line numbers of this file stay the same
+                    self.output.push('\n');
+                    continue;
+                }
+                let include_source = (self.read)(&include_path).map_err(|e| ShaderError::Preprocess {
+                    path: name.clone(),
+                    line: line_number as u32,
+                    message: format!("Failed to read included file {}: {}", include_path.display(), e),
+                })?;
+
+                self.output.push_str(&format!("#line 1 {}\n", self.files.len()));
+                self.expand(&include_path, &include_source)?;
+                self.output.push_str(&format!("#line {} {}\n", line_number + 1, index));
+                continue;
+            }
+
+            self.output.push_str(line);
+            self.output.push('\n');
+        }
+        Ok(version_seen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    // Preprocess `path` with the files read from `sources` instead of the disk
+    fn preprocess_sources(path: &str, defines: &[(&str, &str)], sources: &[(&str, &str)]) -> Result<PreprocessedSource, ShaderError> {
+        let sources: HashMap<PathBuf, String> = sources.iter()
+            .map(|(path, source)| (PathBuf::from(path), source.to_string()))
+            .collect();
+        let defines: Vec<(String, String)> = defines.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        preprocess_with(path, &defines, &|path| {
+            sources.get(path).cloned().ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotFound))
+        })
+    }
+
+    #[test]
+    fn injects_defines_after_version() {
+        let result = preprocess_sources("memory/main.frag", &[("LIT", "1"), ("DEBUG_VIEW", "2")], &[
+            ("memory/main.frag", "// Comment\n#version 430 core\nvoid main() {}\n"),
+        ]).unwrap();
+        assert_eq!(result.source, "// Comment\n#version 430 core\n#define LIT 1\n#define DEBUG_VIEW 2\n#line 3 0\nvoid main() {}\n");
+        assert_eq!(result.files, vec!["memory/main.frag"]);
+
+        // Without a #version the defines go first
+        let result = preprocess_sources("memory/main.frag", &[("LIT", "1")], &[
+            ("memory/main.frag", "void main() {}\n"),
+        ]).unwrap();
+        assert_eq!(result.source, "#define LIT 1\n#line 1 0\nvoid main() {}\n");
+    }
+
+    #[test]
+    fn numbers_lines_by_file() {
+        let result = preprocess_sources("memory/main.frag", &[], &[
+            ("memory/main.frag", "#version 430 core\n#include \"lib/light.glsl\"\nvoid main() {}\n"),
+            ("memory/lib/light.glsl", "float light() { return 1.0; }\n"),
+        ]).unwrap();
+        assert_eq!(result.source, "#version 430 core\n#line 2 0\n#line 1 1\nfloat light() { return 1.0; }\n#line 3 0\nvoid main() {}\n");
+        assert_eq!(result.files, vec!["memory/main.frag", "memory/lib/light.glsl"]);
+    }
+
+    #[test]
+    fn includes_every_file_once() {
+        // Both include the other, and main includes common twice
+        let result = preprocess_sources("memory/main.frag", &[], &[
+            ("memory/main.frag", "#include <common.glsl>\n#include \"common.glsl\"\nmain\n"),
+            ("memory/common.glsl", "#include \"main.frag\"\ncommon\n"),
+        ]).unwrap();
+        assert_eq!(result.source, "#line 1 0\n#line 1 1\n\ncommon\n#line 2 0\n\nmain\n");
+        assert_eq!(result.files, vec!["memory/main.frag", "memory/common.glsl"]);
+    }
+
+    #[test]
+    fn errors_name_the_including_line() {
+        let error = preprocess_sources("memory/main.frag", &[], &[
+            ("memory/main.frag", "#version 430 core\n#include \"missing.glsl\"\n"),
+        ]).err().unwrap();
+        match error {
+            ShaderError::Preprocess { path, line, .. } => assert_eq!((path.as_str(), line), ("memory/main.frag", 2)),
+            error => panic!("{}", error),
+        }
+
+        let error = preprocess_sources("memory/main.frag", &[], &[
+            ("memory/main.frag", "#include missing.glsl\n"),
+        ]).err().unwrap();
+        assert!(matches!(error, ShaderError::Preprocess { line: 1, .. }), "{}", error);
+    }
+
+    #[test]
+    fn errors_keep_why_an_include_could_not_be_read() {
+        let error = preprocess_with("memory/main.frag", &[], &|path| match path.to_str() {
+            Some("memory/main.frag") => Ok("#include \"secret.glsl\"\n".to_string()),
+            _ => Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied)),
+        }).err().unwrap();
+        let expected = std::io::Error::from(std::io::ErrorKind::PermissionDenied).to_string();
+        assert!(error.to_string().contains(&expected), "{}", error);
+    }
+}
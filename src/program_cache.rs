@@ -0,0 +1,160 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use crate::util;
+
+// On-disk cache of linked program binaries. A cache file holds the 4-byte binary format followed by
+// the binary itself. It is named `program-contents.bin`, after a hash of which program it is and a hash of
+// everything that affects the result of linking, so the binaries left behind by edits can be found and removed.
+
+// 64-bit FNV-1a, used instead of std's hasher since cache keys have to stay the same between builds
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3))
+}
+
+// The program is told apart by the stage and file of each shader along with the defines, and its contents
+// by the preprocessed sources and the driver identity. `sources` holds (stage, file, source) for each shader.
+pub unsafe fn cache_key(sources: &[(gl::types::GLenum, &str, &str)], defines: &[(String, String)]) -> String {
+    let mut program = 0xcbf2_9ce4_8422_2325;
+    for (name, value) in defines {
+        program = fnv1a(program, name.as_bytes());
+        program = fnv1a(program, &[0]);
+        program = fnv1a(program, value.as_bytes());
+        program = fnv1a(program, &[0]);
+    }
+    for (shader_type, file, _) in sources {
+        program = fnv1a(program, &shader_type.to_le_bytes());
+        program = fnv1a(program, file.as_bytes());
+        program = fnv1a(program, &[0]);
+    }
+
+    let mut contents = program;
+    for name in [gl::VENDOR, gl::RENDERER, gl::VERSION] {
+        contents = fnv1a(contents, util::get_gl_string(name).as_bytes());
+        contents = fnv1a(contents, &[0]);
+    }
+    for (_, _, source) in sources {
+        contents = fnv1a(contents, source.as_bytes());
+        contents = fnv1a(contents, &[0]);
+    }
+    format!("{:016x}-{:016x}", program, contents)
+}
+
+fn cache_path(dir: &Path, key: &str) -> PathBuf {
+    dir.join(format!("{}.bin", key))
+}
+
+// Whether the driver can hand out program binaries at all
+pub unsafe fn is_supported() -> bool {
+    let mut formats = 0;
+    gl::GetIntegerv(gl::NUM_PROGRAM_BINARY_FORMATS, &mut formats);
+    formats > 0
+}
+
+// Try to load a cached binary into the program. Returns false if there is none, or if the driver
+// rejects it, e.g. after a driver update. The program must then be compiled and linked as usual.
+pub unsafe fn load(dir: &Path, key: &str, program_id: u32) -> bool {
+    let data = match fs::read(cache_path(dir, key)) {
+        Ok(data) if data.len() > 4 => data,
+        _ => return false,
+    };
+    let format = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+    let binary = &data[4..];
+    gl::ProgramBinary(program_id, format, binary.as_ptr() as *const _, binary.len() as i32);
+
+    let mut success = i32::from(gl::FALSE);
+    gl::GetProgramiv(program_id, gl::LINK_STATUS, &mut success);
+    success == i32::from(gl::TRUE)
+}
+
+// Store the binary of a successfully linked program. Failing to write the cache is not an error,
+// the program is just compiled again next time.
+pub unsafe fn store(dir: &Path, key: &str, program_id: u32) {
+    let mut length = 0;
+    gl::GetProgramiv(program_id, gl::PROGRAM_BINARY_LENGTH, &mut length);
+    if length <= 0 {
+        return;
+    }
+    let mut binary = vec![0u8; length as usize];
+    let mut format = 0;
+    let mut written = 0;
+    gl::GetProgramBinary(program_id, length, &mut written, &mut format, binary.as_mut_ptr() as *mut _);
+    binary.truncate(written.max(0) as usize);
+
+    let mut data = format.to_le_bytes().to_vec();
+    data.extend_from_slice(&binary);
+    if let Err(e) = fs::create_dir_all(dir).and_then(|_| fs::write(cache_path(dir, key), data)) {
+        println!("Warning: failed to write shader cache {}: {}", dir.display(), e);
+        return;
+    }
+    prune(dir, key);
+}
+
+// Whether this is one of the 16 digit hashes cache keys are made of
+fn is_hash(s: &str) -> bool {
+    s.len() == 16 && s.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+// Whether a file in the cache directory is a binary that the one stored under `key` replaces: the same program
+// built from other sources or by another driver, or a binary named by a single hash, from before programs were
+// told apart. Files that aren't named like cache binaries are never stale.
+fn is_stale(file_name: &str, key: &str) -> bool {
+    let stem = match file_name.strip_suffix(".bin") {
+        Some(stem) if stem != key => stem,
+        _ => return false,
+    };
+    match (stem.split_once('-'), key.split_once('-')) {
+        (Some((program, contents)), Some((key_program, _))) => is_hash(program) && is_hash(contents) && program == key_program,
+        (None, _) => is_hash(stem),
+        _ => false,
+    }
+}
+
+fn prune(dir: &Path, key: &str) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        if entry.file_name().to_str().is_some_and(|name| is_stale(name, key)) {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAM  : &str = "0123456789abcdef";
+    const CONTENTS : &str = "fedcba9876543210";
+
+    #[test]
+    fn keys_are_made_of_two_hashes() {
+        assert!(is_hash(PROGRAM));
+        assert!(!is_hash("0123456789abcde"));
+        assert!(!is_hash("0123456789abcdeg"));
+        assert_eq!(cache_path(Path::new("cache"), &format!("{}-{}", PROGRAM, CONTENTS)),
+            Path::new("cache").join(format!("{}-{}.bin", PROGRAM, CONTENTS)));
+    }
+
+    #[test]
+    fn prunes_the_programs_other_binaries() {
+        let key = format!("{}-{}", PROGRAM, CONTENTS);
+        assert!(is_stale(&format!("{}-0000000000000000.bin", PROGRAM), &key));
+        assert!(is_stale(&format!("{}.bin", CONTENTS), &key), "Binaries from before programs were told apart");
+
+        assert!(!is_stale(&format!("{}.bin", key), &key), "The binary just stored");
+        assert!(!is_stale(&format!("0000000000000000-{}.bin", CONTENTS), &key), "Another program");
+        assert!(!is_stale(&format!("{}-0000000000000000.txt", PROGRAM), &key));
+    }
+
+    #[test]
+    fn leaves_files_not_named_like_binaries_alone() {
+        let key = format!("{}-{}", PROGRAM, CONTENTS);
+        for name in ["notes.bin", "a-b.bin", "old-ones.bin", ".bin"] {
+            assert!(!is_stale(name, &key), "{}", name);
+        }
+        assert!(!is_stale(&format!("{}-backup.bin", PROGRAM), &key));
+        assert!(!is_stale(&format!("{}-{}-copy.bin", PROGRAM, CONTENTS), &key));
+    }
+}
@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+
+// An active uniform of a linked program
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct UniformInfo {
+    pub name        : String,  // Arrays are listed without the "[0]" suffix GL reports for them
+    pub location    : i32,     // -1 for members of uniform blocks
+    pub gl_type     : gl::types::GLenum,
+    pub size        : i32,     // Number of array elements, 1 for non-arrays
+    pub block_index : i32,     // Index into `uniform_blocks`, -1 for uniforms outside of blocks
+    pub offset      : i32,     // Byte offset inside the uniform block, -1 outside of blocks
+}
+
+// An active vertex shader input
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct AttributeInfo {
+    pub name     : String,
+    pub location : i32,
+    pub gl_type  : gl::types::GLenum,
+    pub size     : i32,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct UniformBlockInfo {
+    pub name      : String,
+    pub binding   : i32,         // The uniform buffer binding point the block reads from
+    pub data_size : i32,         // Size of the block in bytes
+    pub members   : Vec<String>, // Names of the uniforms in the block
+}
+
+// Everything a linked program exposes to the outside, sorted by name (blocks by index)
+#[derive(Debug, Clone, Default)]
+pub struct ProgramReflection {
+    pub uniforms       : Vec<UniformInfo>,
+    pub attributes     : Vec<AttributeInfo>,
+    pub uniform_blocks : Vec<UniformBlockInfo>,
+}
+
+#[allow(dead_code)]
+impl ProgramReflection {
+    pub fn uniform(&self, name: &str) -> Option<&UniformInfo> {
+        self.uniforms.iter().find(|uniform| uniform.name == name)
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&AttributeInfo> {
+        self.attributes.iter().find(|attribute| attribute.name == name)
+    }
+
+    pub fn uniform_block(&self, name: &str) -> Option<&UniformBlockInfo> {
+        self.uniform_blocks.iter().find(|block| block.name == name)
+    }
+
+    // Uniforms outside of blocks by name, which is what the uniform setters look up
+    pub fn uniform_locations(&self) -> HashMap<String, UniformInfo> {
+        self.uniforms.iter()
+            .filter(|uniform| uniform.location >= 0)
+            .map(|uniform| (uniform.name.clone(), uniform.clone()))
+            .collect()
+    }
+}
+
+unsafe fn resource_count(program_id: u32, interface: gl::types::GLenum) -> u32 {
+    let mut count = 0;
+    gl::GetProgramInterfaceiv(program_id, interface, gl::ACTIVE_RESOURCES, &mut count);
+    count.max(0) as u32
+}
+
+unsafe fn resource_properties<const N: usize>(program_id: u32, interface: gl::types::GLenum, index: u32, properties: [gl::types::GLenum; N]) -> [i32; N] {
+    let mut values = [0; N];
+    gl::GetProgramResourceiv(program_id, interface, index, N as i32, properties.as_ptr(),
+        N as i32, std::ptr::null_mut(), values.as_mut_ptr());
+    values
+}
+
+unsafe fn resource_name(program_id: u32, interface: gl::types::GLenum, index: u32) -> String {
+    let [length] = resource_properties(program_id, interface, index, [gl::NAME_LENGTH]);
+    let mut name = vec![0u8; length.max(1) as usize];
+    let mut written = 0;
+    gl::GetProgramResourceName(program_id, interface, index, length, &mut written, name.as_mut_ptr() as *mut gl::types::GLchar);
+    name.truncate(written.max(0) as usize);
+    String::from_utf8_lossy(&name).into_owned()
+}
+
+// Enumerate the active uniforms, vertex inputs and uniform blocks of a linked program
+pub unsafe fn reflect_program(program_id: u32) -> ProgramReflection {
+    let mut uniforms: Vec<UniformInfo> = (0..resource_count(program_id, gl::UNIFORM)).map(|index| {
+        let [gl_type, size, location, block_index, offset] = resource_properties(program_id, gl::UNIFORM, index,
+            [gl::TYPE, gl::ARRAY_SIZE, gl::LOCATION, gl::BLOCK_INDEX, gl::OFFSET]);
+        let name = resource_name(program_id, gl::UNIFORM, index);
+        UniformInfo {
+            name: name.strip_suffix("[0]").unwrap_or(&name).to_string(),
+            location,
+            gl_type: gl_type as gl::types::GLenum,
+            size,
+            block_index,
+            offset,
+        }
+    }).collect();
+    uniforms.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut attributes: Vec<AttributeInfo> = (0..resource_count(program_id, gl::PROGRAM_INPUT)).filter_map(|index| {
+        let [gl_type, size, location] = resource_properties(program_id, gl::PROGRAM_INPUT, index,
+            [gl::TYPE, gl::ARRAY_SIZE, gl::LOCATION]);
+        // Built-in inputs like gl_VertexID have no location
+        if location < 0 {
+            return None;
+        }
+        Some(AttributeInfo {
+            name: resource_name(program_id, gl::PROGRAM_INPUT, index),
+            location,
+            gl_type: gl_type as gl::types::GLenum,
+            size,
+        })
+    }).collect();
+    attributes.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let uniform_blocks = (0..resource_count(program_id, gl::UNIFORM_BLOCK)).map(|index| {
+        let [binding, data_size] = resource_properties(program_id, gl::UNIFORM_BLOCK, index,
+            [gl::BUFFER_BINDING, gl::BUFFER_DATA_SIZE]);
+        UniformBlockInfo {
+            name: resource_name(program_id, gl::UNIFORM_BLOCK, index),
+            binding,
+            data_size,
+            members: uniforms.iter()
+                .filter(|uniform| uniform.block_index == index as i32)
+                .map(|uniform| uniform.name.clone())
+                .collect(),
+        }
+    }).collect();
+
+    ProgramReflection { uniforms, attributes, uniform_blocks }
+}
+
+// Whether the type is a sampler or image, which are set like an int
+pub fn is_opaque_type(gl_type: gl::types::GLenum) -> bool {
+    matches!(gl_type,
+        gl::SAMPLER_1D | gl::SAMPLER_2D | gl::SAMPLER_3D | gl::SAMPLER_CUBE |
+        gl::SAMPLER_1D_SHADOW | gl::SAMPLER_2D_SHADOW | gl::SAMPLER_CUBE_SHADOW |
+        gl::SAMPLER_1D_ARRAY | gl::SAMPLER_2D_ARRAY | gl::SAMPLER_2D_ARRAY_SHADOW |
+        gl::SAMPLER_2D_MULTISAMPLE | gl::SAMPLER_BUFFER |
+        gl::INT_SAMPLER_2D | gl::INT_SAMPLER_3D | gl::UNSIGNED_INT_SAMPLER_2D | gl::UNSIGNED_INT_SAMPLER_3D |
+        gl::IMAGE_1D | gl::IMAGE_2D | gl::IMAGE_3D | gl::IMAGE_CUBE | gl::IMAGE_2D_ARRAY | gl::IMAGE_BUFFER |
+        gl::INT_IMAGE_2D | gl::INT_IMAGE_3D | gl::UNSIGNED_INT_IMAGE_2D | gl::UNSIGNED_INT_IMAGE_3D
+    )
+}
+
+// The GLSL name of a type, for error messages
+pub fn gl_type_name(gl_type: gl::types::GLenum) -> String {
+    match gl_type {
+        gl::FLOAT             => "float",
+        gl::FLOAT_VEC2        => "vec2",
+        gl::FLOAT_VEC3        => "vec3",
+        gl::FLOAT_VEC4        => "vec4",
+        gl::INT               => "int",
+        gl::INT_VEC2          => "ivec2",
+        gl::INT_VEC3          => "ivec3",
+        gl::INT_VEC4          => "ivec4",
+        gl::UNSIGNED_INT      => "uint",
+        gl::UNSIGNED_INT_VEC2 => "uvec2",
+        gl::UNSIGNED_INT_VEC3 => "uvec3",
+        gl::UNSIGNED_INT_VEC4 => "uvec4",
+        gl::BOOL              => "bool",
+        gl::FLOAT_MAT2        => "mat2",
+        gl::FLOAT_MAT3        => "mat3",
+        gl::FLOAT_MAT4        => "mat4",
+        gl::SAMPLER_2D        => "sampler2D",
+        gl::SAMPLER_3D        => "sampler3D",
+        gl::SAMPLER_CUBE      => "samplerCube",
+        gl::IMAGE_2D          => "image2D",
+        gl::IMAGE_3D          => "image3D",
+        t if is_opaque_type(t) => "sampler or image",
+        t => return format!("0x{:04X}", t),
+    }.to_string()
+}
@@ -0,0 +1,70 @@
+use crate::gl_object::{Framebuffer, Renderbuffer};
+
+// An offscreen framebuffer with a color and a depth attachment, for rendering without a window
+pub struct RenderTarget {
+    framebuffer : Framebuffer,
+    color       : Renderbuffer,
+    depth       : Renderbuffer,
+    pub width   : u32,
+    pub height  : u32,
+}
+
+impl RenderTarget {
+    pub unsafe fn new(width: u32, height: u32) -> Result<RenderTarget, String> {
+        let target = RenderTarget {
+            framebuffer : Framebuffer::new(),
+            color       : Renderbuffer::new(),
+            depth       : Renderbuffer::new(),
+            width,
+            height,
+        };
+        target.allocate()?;
+        Ok(target)
+    }
+
+    unsafe fn allocate(&self) -> Result<(), String> {
+        gl::BindRenderbuffer(gl::RENDERBUFFER, self.color.id());
+        gl::RenderbufferStorage(gl::RENDERBUFFER, gl::RGBA8, self.width as i32, self.height as i32);
+        gl::BindRenderbuffer(gl::RENDERBUFFER, self.depth.id());
+        // Floating point depth, so reverse-Z projections get their precision
+        gl::RenderbufferStorage(gl::RENDERBUFFER, gl::DEPTH_COMPONENT32F, self.width as i32, self.height as i32);
+        gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer.id());
+        gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::RENDERBUFFER, self.color.id());
+        gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::RENDERBUFFER, self.depth.id());
+        let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            return Err(format!("Offscreen framebuffer of {}x{} is incomplete: 0x{:x}", self.width, self.height, status));
+        }
+        Ok(())
+    }
+
+    // Change the size, throwing away the contents
+    #[allow(dead_code)]
+    pub unsafe fn resize(&mut self, width: u32, height: u32) -> Result<(), String> {
+        self.width = width;
+        self.height = height;
+        self.allocate()
+    }
+
+    // Render into this target from now on, covering all of it
+    pub unsafe fn bind(&self) {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer.id());
+        gl::Viewport(0, 0, self.width as i32, self.height as i32);
+    }
+
+    // Copy the color attachment back to the CPU, top row first
+    pub unsafe fn read_pixels(&self) -> image::RgbaImage {
+        let mut pixels = vec![0u8; (self.width * self.height * 4) as usize];
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.framebuffer.id());
+        gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+        gl::ReadPixels(0, 0, self.width as i32, self.height as i32, gl::RGBA, gl::UNSIGNED_BYTE, pixels.as_mut_ptr() as *mut _);
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+
+        let image = image::RgbaImage::from_raw(self.width, self.height, pixels).unwrap();
+        // OpenGL puts the first row at the bottom
+        image::imageops::flip_vertical(&image)
+    }
+}
@@ -0,0 +1,319 @@
+extern crate nalgebra_glm as glm;
+use std::ptr;
+
+use crate::chunked_terrain::{ChunkedTerrain, HeightSource};
+use crate::mesh;
+use crate::scene_graph::{self, SceneNode, Node};
+use crate::shader::{Shader, ShaderError};
+use crate::shader_library::{ShaderLibrary, Material};
+use crate::terrain::HeightGrid;
+use crate::toolbox;
+use crate::uniform_buffer::{self, UniformBuffer, FrameUniforms, LightUniforms};
+use crate::vao::{Vao, VertexLayout, BufferUsage, DynamicMesh};
+
+pub const CLEAR_COLOR: [f32; 4] = [0.2, 0.3, 0.3, 1.0]; // moon raker, full opacity
+
+// What the helicopter flies over
+#[derive(Clone, Copy, PartialEq)]
+pub enum TerrainSource {
+    Model,    // The lunar surface model
+    Streamed, // Generated terrain, streamed in chunks around the camera
+    None,
+}
+
+// What goes into the scene, set from the command line
+#[derive(Clone)]
+pub struct SceneOptions {
+    pub terrain : TerrainSource,
+}
+
+impl SceneOptions {
+    // --streamed-terrain picks the generated terrain, and --no-terrain leaves it out
+    pub fn from_args(args: &[String]) -> SceneOptions {
+        let terrain = if args.iter().any(|arg| arg == "--no-terrain") {
+            TerrainSource::None
+        } else if args.iter().any(|arg| arg == "--streamed-terrain") {
+            TerrainSource::Streamed
+        } else {
+            TerrainSource::Model
+        };
+        SceneOptions { terrain }
+    }
+}
+
+// Where the scene is seen from, for one frame
+pub struct View {
+    pub view            : glm::Mat4,
+    pub projection      : glm::Mat4,
+    pub camera_position : glm::Vec3,
+    pub reverse_z       : bool, // Whether the projection puts the near plane at depth 1 and the far plane at 0
+}
+
+// The terrain with the helicopter flying over it, along with everything needed to draw it
+pub struct Scene {
+    pub root_node        : Node,
+    #[allow(dead_code)]
+    pub terrain_node     : Node,
+    pub body_node        : Node,
+    #[allow(dead_code)]
+    pub door_node        : Node,
+    pub main_rotor_node  : Node,
+    pub tail_rotor_node  : Node,
+
+    pub dynamic_terrain  : Option<DynamicMesh>,    // The terrain model, which can be deformed at runtime
+    pub streamed_terrain : Option<ChunkedTerrain>,
+    terrain_heights      : Option<HeightGrid>,     // For keeping cameras above the terrain model
+    pub renderer         : Renderer,
+    _helicopter_vaos     : Vec<Vao>,
+}
+
+// Draws a scene graph with the shader variants its materials ask for
+pub struct Renderer {
+    pub shaders        : ShaderLibrary,
+    pub light_uniforms : UniformBuffer<LightUniforms>, // Call update() on this to change the lights
+    frame_uniforms     : UniformBuffer<FrameUniforms>,
+}
+
+// Every variant of the shader checks that its uniform blocks match ours
+fn check_uniform_blocks(shader: &Shader) -> Result<(), ShaderError> {
+    uniform_buffer::check_layout::<FrameUniforms>(shader.reflection())?;
+    uniform_buffer::check_layout::<LightUniforms>(shader.reflection())
+}
+
+// The OpenGL state the scene is drawn with. Call this once after creating the context.
+pub unsafe fn setup_gl_state() {
+    gl::Enable(gl::DEPTH_TEST);
+    gl::DepthFunc(gl::LESS);
+    gl::Enable(gl::CULL_FACE);
+    gl::Disable(gl::MULTISAMPLE);
+    gl::Enable(gl::BLEND);
+    gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+}
+
+unsafe fn draw_scene(node: &scene_graph::SceneNode, shaders: &mut ShaderLibrary, current_program: &mut u32) -> Result<(), ShaderError> {
+    if node.index_count > 0 {
+        let shader = shaders.get(&node.material)?;
+        if shader.program_id() != *current_program {
+            shader.activate();
+            *current_program = shader.program_id();
+        }
+        shader.set_uniform("model", &node.current_transformation_matrix)?;
+        shader.set_uniform("tint", &node.tint)?;
+        gl::BindVertexArray(node.vao_id);
+        gl::DrawElements(gl::TRIANGLES, node.index_count, node.index_type, ptr::null());
+    }
+    for &child in &node.children {
+        draw_scene(&*child, shaders, current_program)?;
+    }
+    Ok(())
+}
+
+unsafe fn update_node_transformations(node: &mut scene_graph::SceneNode, transformation_so_far: &glm::Mat4) {
+    //Construct the correct transformation matrix
+    let mut transformation: glm::Mat4 = glm::identity();
+
+    transformation = glm::translation(&glm::vec3(-node.reference_point.x, -node.reference_point.y, -node.reference_point.z))*transformation;
+
+    transformation = glm::scaling(&node.scale)*transformation;
+
+    transformation = glm::rotation(node.rotation.x, &glm::vec3(1.0, 0.0, 0.0))*transformation;
+    transformation = glm::rotation(node.rotation.y, &glm::vec3(0.0, 1.0, 0.0))*transformation;
+    transformation = glm::rotation(node.rotation.z, &glm::vec3(0.0, 0.0, 1.0))*transformation;
+
+    transformation = glm::translation(&glm::vec3(node.reference_point.x, node.reference_point.y, node.reference_point.z))*transformation;
+
+    transformation = glm::translation(&glm::vec3(node.position.x, node.position.y, node.position.z))*transformation;
+
+    //The parent's transformation goes last, so the node is moved along with its parent
+    transformation = transformation_so_far*transformation; //The first transformation_so_far will be an identity matrix
+
+    //Update the node's transformation matrix
+    node.current_transformation_matrix = transformation;
+
+    for &child in &node.children {
+        update_node_transformations(&mut *child, &node.current_transformation_matrix);
+    }
+}
+
+impl Scene {
+    // Load the models and build the scene graph. Needs a current OpenGL context.
+    pub unsafe fn new(options: &SceneOptions) -> Scene {
+        let mut streamed_terrain = if options.terrain == TerrainSource::Streamed {
+            Some(ChunkedTerrain::new(HeightSource::Noise(Default::default()), Default::default()))
+        } else {
+            None
+        };
+
+        //Load models
+        let terrain = match options.terrain {
+            TerrainSource::Model => Some(mesh::Terrain::load("./resources/lunarsurface.obj")),
+            TerrainSource::Streamed | TerrainSource::None => None,
+        };
+        let helicopter = mesh::Helicopter::load("./resources/helicopter.obj");
+
+        // Taken before the mesh is handed over, so it doesn't follow craters. Those only lower the ground.
+        let terrain_heights = terrain.as_ref().map(|terrain| HeightGrid::from_mesh(terrain, 8.0));
+
+        //Build vaos
+        // The terrain can be deformed at runtime, so keep it around and upload it as dynamic
+        let dynamic_terrain = terrain.map(|terrain| DynamicMesh::new(terrain, &VertexLayout::compact().usage(BufferUsage::Dynamic)));
+        let vao_body = Vao::from_mesh(&helicopter.body, &VertexLayout::standard());
+        let vao_door = Vao::from_mesh(&helicopter.door, &VertexLayout::standard());
+        let vao_main_rotor = Vao::from_mesh(&helicopter.main_rotor, &VertexLayout::standard());
+        let vao_tail_rotor = Vao::from_mesh(&helicopter.tail_rotor, &VertexLayout::standard());
+
+        let mut root_node = SceneNode::new();
+        let mut terrain_node = match &dynamic_terrain {
+            Some(terrain) => SceneNode::from_mesh_vao(&terrain.vao),
+            None => SceneNode::new(),
+        };
+        // Fade the terrain out into the distance
+        terrain_node.material = Material::default().with_flag("FOG", true);
+        if let Some(streamed_terrain) = &mut streamed_terrain {
+            streamed_terrain.material = terrain_node.material.clone();
+            terrain_node.add_child(&streamed_terrain.root);
+        }
+        let mut body_node = SceneNode::from_mesh_vao(&vao_body);
+        let door_node = SceneNode::from_mesh_vao(&vao_door);
+        let mut main_rotor_node = SceneNode::from_mesh_vao(&vao_main_rotor);
+        let mut tail_rotor_node = SceneNode::from_mesh_vao(&vao_tail_rotor);
+
+        root_node.add_child(&terrain_node);
+        terrain_node.add_child(&body_node);
+        body_node.add_child(&door_node);
+        body_node.add_child(&main_rotor_node);
+        body_node.add_child(&tail_rotor_node);
+
+        body_node.position = glm::vec3(0.0, 00.0, -40.0);
+        body_node.rotation.y = 3.00;
+
+        tail_rotor_node.reference_point = glm::vec3(0.35, 2.3, 10.4);
+        main_rotor_node.reference_point = glm::vec3(0.0, 0.0, 0.0);
+
+        let mut renderer = Renderer::new();
+        let shader = renderer.shaders.get(&Material::default()).unwrap_or_else(|e| panic!("{}", e));
+
+        // Make sure every mesh supplies the attributes the shader reads
+        for vao in [&vao_body, &vao_door, &vao_main_rotor, &vao_tail_rotor].iter().copied().chain(dynamic_terrain.iter().map(|terrain| &terrain.vao)) {
+            shader.check_vertex_array(vao.id()).unwrap_or_else(|e| panic!("{}", e));
+        }
+
+        Scene {
+            root_node,
+            terrain_node,
+            body_node,
+            door_node,
+            main_rotor_node,
+            tail_rotor_node,
+            dynamic_terrain,
+            streamed_terrain,
+            terrain_heights,
+            renderer,
+            _helicopter_vaos: vec![vao_body, vao_door, vao_main_rotor, vao_tail_rotor],
+        }
+    }
+
+    // Move everything to where it is `time` seconds in
+    pub fn animate(&mut self, time: f32) {
+        let heading = toolbox::simple_heading_animation(time);
+        self.body_node.position = glm::vec3(heading.x, 0.0, heading.z - 40.0);
+        self.body_node.rotation = glm::vec3(heading.pitch, heading.yaw, heading.roll);
+
+        self.main_rotor_node.rotation.y = time * 15.0;
+        self.tail_rotor_node.rotation.x = time * 30.0;
+    }
+
+    // Load the terrain chunks in view right away, instead of over the following frames
+    pub unsafe fn load_terrain(&mut self, camera_position: &glm::Vec3) {
+        if let Some(streamed_terrain) = &mut self.streamed_terrain {
+            streamed_terrain.update_blocking(camera_position);
+        }
+    }
+
+    // Color the terrain, whether it is the model or streamed
+    pub fn color_terrain(&mut self, mode: &mesh::ColorMode) -> Result<(), String> {
+        if let Some(streamed_terrain) = &mut self.streamed_terrain {
+            streamed_terrain.set_color_mode(mode)?;
+        }
+        if let Some(terrain) = &mut self.dynamic_terrain {
+            terrain.modify(|mesh| {
+                mesh.recolor(mode);
+                Some(0..mesh.vertex_count())
+            });
+        }
+        Ok(())
+    }
+
+    // Height of the ground at (x, z), if there is terrain there
+    pub fn terrain_height(&self, x: f32, z: f32) -> Option<f32> {
+        if let Some(heights) = &self.terrain_heights {
+            return heights.height_at(x, z);
+        }
+        self.streamed_terrain.as_ref().and_then(|terrain| terrain.height_at(x, z))
+    }
+
+    // Draw a frame into the bound framebuffer
+    pub unsafe fn render(&mut self, view: &View, time: f32) {
+        if let Some(streamed_terrain) = &mut self.streamed_terrain {
+            streamed_terrain.update(&view.camera_position);
+        }
+        if let Some(terrain) = &mut self.dynamic_terrain {
+            terrain.sync();
+        }
+
+        self.renderer.render(&mut self.root_node, view, time);
+    }
+}
+
+impl Renderer {
+    pub unsafe fn new() -> Renderer {
+        // The shader is compiled once per combination of options used by the materials in the scene
+        let shaders = ShaderLibrary::new(&["./shaders/simple.vert", "./shaders/simple.frag"])
+            .bool_option("LIT", true)
+            .bool_option("FOG", false)
+            .int_option("DEBUG_VIEW", 0, 0..=2)
+            .cache_dir("./shader_cache")
+            .validate(check_uniform_blocks);
+
+        // Camera and lighting data shared by all programs, bound to fixed binding points
+        let frame_uniforms = UniformBuffer::new(&FrameUniforms {
+            view: glm::identity(),
+            projection: glm::identity(),
+            camera_position: glm::zero(),
+            time: 0.0,
+        });
+        let light_uniforms = UniformBuffer::new(&LightUniforms::default());
+
+        Renderer { shaders, light_uniforms, frame_uniforms }
+    }
+
+    // Clear the bound framebuffer and draw the graph below `root` into it
+    pub unsafe fn render(&mut self, root: &mut SceneNode, view: &View, time: f32) {
+        self.frame_uniforms.update(&FrameUniforms {
+            view: view.view,
+            projection: view.projection,
+            camera_position: view.camera_position,
+            time,
+        });
+
+        // Reverse-Z only gains precision when depth is kept in [0, 1] rather than squeezed from [-1, 1].
+        // Without ClipControl the depth test still works, just with less to gain.
+        if gl::ClipControl::is_loaded() {
+            gl::ClipControl(gl::LOWER_LEFT, if view.reverse_z { gl::ZERO_TO_ONE } else { gl::NEGATIVE_ONE_TO_ONE });
+        }
+        gl::ClearDepth(if view.reverse_z { 0.0 } else { 1.0 });
+        gl::DepthFunc(if view.reverse_z { gl::GREATER } else { gl::LESS });
+
+        let [r, g, b, a] = CLEAR_COLOR;
+        gl::ClearColor(r, g, b, a);
+        gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+
+        gl::Enable(gl::BLEND);
+        gl::Disable(gl::CULL_FACE);
+        gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+
+        update_node_transformations(root, &glm::identity());
+
+        draw_scene(root, &mut self.shaders, &mut 0).unwrap_or_else(|e| panic!("{}", e));
+    }
+}
@@ -3,6 +3,9 @@ extern crate nalgebra_glm as glm;
 use std::mem::ManuallyDrop;
 use std::pin::Pin;
 
+use crate::shader_library::Material;
+use crate::vao::Vao;
+
 // Used to create an unholy abomination upon which you should not cast your gaze. This ended up
 // being a necessity due to wanting to keep the code written by students as "straight forward" as
 // possible. It is very very double plus ungood Rust, and intentionally leaks memory like a sieve.
@@ -21,11 +24,14 @@ pub struct SceneNode {
     pub rotation        : glm::Vec3,   // How I should be rotated
     pub scale           : glm::Vec3,   // How I should be scaled
     pub reference_point : glm::Vec3,   // About which point I shall rotate about
+    pub tint            : glm::Vec4,   // What my vertex colors are multiplied by
+    pub material        : Material,    // Which shader features I am drawn with
 
     pub current_transformation_matrix: glm::Mat4, // The fruits of my labor
 
     pub vao_id      : u32,             // What I should draw
     pub index_count : i32,             // How much of it I shall draw
+    pub index_type  : u32,             // And what my indices look like
 
     pub children: Vec<*mut SceneNode>, // Those I command
 }
@@ -38,9 +44,12 @@ impl SceneNode {
             rotation        : glm::zero(),
             scale           : glm::vec3(1.0, 1.0, 1.0),
             reference_point : glm::zero(),
+            tint            : glm::vec4(1.0, 1.0, 1.0, 1.0),
+            material        : Material::default(),
             current_transformation_matrix: glm::identity(),
             vao_id          : 0,
             index_count     : -1,
+            index_type      : gl::UNSIGNED_INT,
             children        : vec![],
         })))
     }
@@ -51,17 +60,32 @@ impl SceneNode {
             rotation        : glm::zero(),
             scale           : glm::vec3(1.0, 1.0, 1.0),
             reference_point : glm::zero(),
+            tint            : glm::vec4(1.0, 1.0, 1.0, 1.0),
+            material        : Material::default(),
             current_transformation_matrix: glm::identity(),
             vao_id,
             index_count,
+            index_type: gl::UNSIGNED_INT,
             children: vec![],
         })))
     }
 
+    // A node drawing the whole of a VAO made by Vao::from_mesh
+    pub fn from_mesh_vao(vao: &Vao) -> Node {
+        let mut node = SceneNode::from_vao(vao.id(), vao.index_count);
+        node.index_type = vao.index_type;
+        node
+    }
+
     pub fn add_child(&mut self, child: &SceneNode) {
         self.children.push(child as *const SceneNode as *mut SceneNode)
     }
 
+    pub fn remove_child(&mut self, child: &SceneNode) {
+        let child = child as *const SceneNode as *mut SceneNode;
+        self.children.retain(|&c| c != child);
+    }
+
     #[allow(dead_code)]
     pub fn get_child(&mut self, index: usize) -> &mut SceneNode {
         unsafe {
@@ -85,6 +109,7 @@ impl SceneNode {
     Position:  [{:.2}, {:.2}, {:.2}]
     Rotation:  [{:.2}, {:.2}, {:.2}]
     Reference: [{:.2}, {:.2}, {:.2}]
+    Tint:      [{:.2}, {:.2}, {:.2}, {:.2}]
     Current Transformation Matrix:
         {:.2}  {:.2}  {:.2}  {:.2}
         {:.2}  {:.2}  {:.2}  {:.2}
@@ -103,6 +128,10 @@ impl SceneNode {
             self.reference_point.x,
             self.reference_point.y,
             self.reference_point.z,
+            self.tint.x,
+            self.tint.y,
+            self.tint.z,
+            self.tint.w,
             m[0], m[4], m[8],  m[12],
             m[1], m[5], m[9],  m[13],
             m[2], m[6], m[10], m[14],
@@ -1,18 +1,50 @@
-use gl;
+use crate::gl_object::{Program, ShaderObject};
+use crate::preprocessor::preprocess;
+use crate::program_cache;
+use crate::reflection::{reflect_program, gl_type_name, ProgramReflection, UniformInfo};
+use crate::uniform::UniformValue;
 use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
     ptr,
     str,
+    fmt,
     ffi::CString,
-    path::Path,
+    path::{Path, PathBuf},
+    time::SystemTime,
 };
 
 pub struct Shader {
-    pub program_id: u32,
+    program: Program,                                  // Deleted along with the shader
+    source_paths: Vec<String>,                         // The files the program was built from, for reloading
+    watched_files: Vec<(String, Option<SystemTime>)>,  // Every file read while building, with its modification time
+    defines: Vec<(String, String)>,
+    cache_dir: Option<PathBuf>,
+    reflection: ProgramReflection,                     // Active uniforms, attributes and uniform blocks
+    uniforms: HashMap<String, UniformInfo>,            // Active uniforms outside of blocks by name, for the setters
+    missing_uniforms: RefCell<HashSet<String>>,        // Uniforms we have already warned about
 }
 
 pub struct ShaderBuilder {
-    program_id: u32,
-    shaders: Vec::<u32>,
+    program: Program,             // Both the program and the shaders are deleted if building fails
+    shaders: Vec::<ShaderObject>,
+    sources: Vec<PendingSource>,  // Compiled when linking, unless the program binary is cached
+    source_paths: Vec<String>,
+    watched_files: Vec<(String, Option<SystemTime>)>,
+    defines: Vec<(String, String)>,
+    cache_dir: Option<PathBuf>,
+}
+
+// A preprocessed shader stage waiting to be compiled. `files` names the source strings the shader
+// was built from, so source string N in the info log is reported as files[N]
+struct PendingSource {
+    source: String,
+    shader_type: gl::types::GLenum,
+    files: Vec<String>,
+}
+
+fn modified_time(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
 }
 
 #[allow(dead_code)]
@@ -22,28 +54,301 @@ pub enum ShaderType {
     TessellationControl,
     TessellationEvaluation,
     Geometry,
+    Compute,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+// A single message from a shader info log, mapped back to the file it came from
+#[derive(Debug, Clone)]
+pub struct ShaderLogEntry {
+    pub path     : String,
+    pub line     : Option<u32>,
+    pub severity : Severity,
+    pub message  : String,
+}
+
+#[derive(Debug)]
+pub enum ShaderError {
+    Io { path: String, message: String },
+    UnknownExtension(String),
+    Preprocess { path: String, line: u32, message: String },
+    Compile { path: String, log: String, entries: Vec<ShaderLogEntry> },
+    Link { log: String },
+    Validate { log: String },
+    Uniform { name: String, message: String },
+    Layout { block: String, message: String },
+    MissingAttribute { name: String, location: i32 },
+    Feature { name: String, message: String },
+}
+
+impl fmt::Display for ShaderLogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}: {}", self.path, line, severity, self.message),
+            None => write!(f, "{}: {}: {}", self.path, severity, self.message),
+        }
+    }
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShaderError::Io { path, message } => write!(f, "Failed to read shader source {}: {}", path, message),
+            ShaderError::UnknownExtension(path) => write!(f, "Failed to parse file extension of shader {}", path),
+            ShaderError::Preprocess { path, line, message } => write!(f, "{}:{}: {}", path, line, message),
+            ShaderError::Compile { path, log, entries } => {
+                write!(f, "Failed to compile shader {}", path)?;
+                if entries.is_empty() {
+                    return write!(f, "\n{}", log);
+                }
+                for entry in entries {
+                    write!(f, "\n    {}", entry)?;
+                }
+                Ok(())
+            },
+            ShaderError::Link { log } => write!(f, "Failed to link shader program\n{}", log.trim_end()),
+            ShaderError::Validate { log } => write!(f, "Shader program failed validation\n{}", log.trim_end()),
+            ShaderError::Uniform { name, message } => write!(f, "Failed to set uniform {}: {}", name, message),
+            ShaderError::Layout { block, message } => write!(f, "Uniform block {} doesn't match its Rust struct: {}", block, message),
+            ShaderError::MissingAttribute { name, location } =>
+                write!(f, "The shader reads attribute {} from location {}, but the vertex array doesn't enable it", name, location),
+            ShaderError::Feature { name, message } => write!(f, "Invalid shader option {}: {}", name, message),
+        }
+    }
+}
+
+impl std::error::Error for ShaderError {}
+
+// Read the complete info log of a shader object
+unsafe fn shader_info_log(shader_id: u32) -> String {
+    let mut length = 0;
+    gl::GetShaderiv(shader_id, gl::INFO_LOG_LENGTH, &mut length);
+    let mut info_log = vec![0u8; length.max(1) as usize];
+    let mut written = 0;
+    gl::GetShaderInfoLog(shader_id, length, &mut written, info_log.as_mut_ptr() as *mut gl::types::GLchar);
+    info_log.truncate(written.max(0) as usize);
+    String::from_utf8_lossy(&info_log).into_owned()
+}
+
+// Read the complete info log of a program object
+unsafe fn program_info_log(program_id: u32) -> String {
+    let mut length = 0;
+    gl::GetProgramiv(program_id, gl::INFO_LOG_LENGTH, &mut length);
+    let mut info_log = vec![0u8; length.max(1) as usize];
+    let mut written = 0;
+    gl::GetProgramInfoLog(program_id, length, &mut written, info_log.as_mut_ptr() as *mut gl::types::GLchar);
+    info_log.truncate(written.max(0) as usize);
+    String::from_utf8_lossy(&info_log).into_owned()
+}
+
+// Drivers disagree on the log format. The ones we know of are
+//   Mesa:          0:12(5): error: `foo' undeclared
+//   NVIDIA:        0(12) : error C1008: undefined variable "foo"
+//   AMD and Intel: ERROR: 0:12: 'foo' : undeclared identifier
+// where 0 is the source string number and 12 the line.
+pub fn parse_info_log(log: &str, files: &[String]) -> Vec<ShaderLogEntry> {
+    let file_name = |index: usize| files.get(index).cloned().unwrap_or_else(|| format!("<source {}>", index));
+    log.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let (prefix_severity, rest) = strip_severity(line);
+            match parse_location(rest) {
+                Some((file, line_number, message)) => {
+                    let (severity, message) = strip_severity(message);
+                    ShaderLogEntry {
+                        path     : file_name(file),
+                        line     : Some(line_number),
+                        severity : prefix_severity.or(severity).unwrap_or(Severity::Error),
+                        message  : message.to_string(),
+                    }
+                },
+                None => ShaderLogEntry {
+                    path     : file_name(0),
+                    line     : None,
+                    severity : prefix_severity.unwrap_or(Severity::Error),
+                    message  : rest.to_string(),
+                },
+            }
+        })
+        .collect()
+}
+
+// Split "error: message", "ERROR: message" or "error C1008: message" into severity and message
+fn strip_severity(text: &str) -> (Option<Severity>, &str) {
+    let lower = text.to_ascii_lowercase();
+    let severity = if lower.starts_with("error") {
+        Severity::Error
+    } else if lower.starts_with("warning") {
+        Severity::Warning
+    } else {
+        return (None, text);
+    };
+    match text.find(':') {
+        Some(i) => (Some(severity), text[i + 1..].trim_start()),
+        None => (Some(severity), text),
+    }
+}
+
+// Parse "0:12(5): rest", "0(12) : rest" or "0:12: rest" into (source string, line, rest)
+fn parse_location(text: &str) -> Option<(usize, u32, &str)> {
+    let digits = |s: &str| s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+
+    let n = digits(text);
+    let file = text[..n].parse().ok()?;
+    let rest = &text[n..];
+
+    let (line, rest) = if let Some(rest) = rest.strip_prefix(':') {
+        let n = digits(rest);
+        (rest[..n].parse().ok()?, &rest[n..])
+    } else if let Some(rest) = rest.strip_prefix('(') {
+        let n = digits(rest);
+        (rest[..n].parse().ok()?, rest[n..].strip_prefix(')')?)
+    } else {
+        return None;
+    };
+
+    // Skip the column number Mesa puts after the line
+    let rest = match rest.strip_prefix('(') {
+        Some(column) => &column[column.find(')')? + 1..],
+        None => rest,
+    };
+    Some((file, line, rest.trim_start().strip_prefix(':')?.trim_start()))
 }
 
 impl Shader {
-    // Make sure the shader is active before calling this
-    pub unsafe fn get_uniform_location(&self, name: &str) -> i32 {
-        let name_cstr = CString::new(name).expect("CString::new failed");
-        gl::GetUniformLocation(self.program_id, name_cstr.as_ptr())
+    pub fn program_id(&self) -> u32 {
+        self.program.id()
+    }
+
+    // Returns -1 if there is no active uniform with that name
+    #[allow(dead_code)]
+    pub fn get_uniform_location(&self, name: &str) -> i32 {
+        self.uniforms.get(name).map_or(-1, |uniform| uniform.location)
+    }
+
+    #[allow(dead_code)]
+    pub fn reflection(&self) -> &ProgramReflection {
+        &self.reflection
+    }
+
+    // Check that the vertex array enables every attribute location the vertex shader reads from
+    pub unsafe fn check_vertex_array(&self, vao_id: u32) -> Result<(), ShaderError> {
+        let mut previous_vao = 0;
+        gl::GetIntegerv(gl::VERTEX_ARRAY_BINDING, &mut previous_vao);
+        gl::BindVertexArray(vao_id);
+
+        let missing = self.reflection.attributes.iter().find(|attribute| {
+            let mut enabled = 0;
+            gl::GetVertexAttribiv(attribute.location as u32, gl::VERTEX_ATTRIB_ARRAY_ENABLED, &mut enabled);
+            enabled == 0
+        });
+
+        gl::BindVertexArray(previous_vao as u32);
+        match missing {
+            Some(attribute) => Err(ShaderError::MissingAttribute { name: attribute.name.clone(), location: attribute.location }),
+            None => Ok(()),
+        }
+    }
+
+    pub unsafe fn set_uniform<T: UniformValue>(&self, name: &str, value: &T) -> Result<(), ShaderError> {
+        self.set_uniform_array(name, std::slice::from_ref(value))
+    }
+
+    // Set the first values.len() elements of a uniform array. Setting a uniform that isn't active in
+    // the program, e.g. because the compiler optimized it away, only warns the first time.
+    pub unsafe fn set_uniform_array<T: UniformValue>(&self, name: &str, values: &[T]) -> Result<(), ShaderError> {
+        let uniform = match self.uniforms.get(name) {
+            Some(uniform) => uniform,
+            None => {
+                if self.missing_uniforms.borrow_mut().insert(name.to_string()) {
+                    println!("Warning: {} is not an active uniform of shader program {}", name, self.program.id());
+                }
+                return Ok(());
+            },
+        };
+        if !T::accepts(uniform.gl_type) {
+            return Err(ShaderError::Uniform {
+                name: name.to_string(),
+                message: format!("the uniform is a {}, but was given a {}", gl_type_name(uniform.gl_type), std::any::type_name::<T>()),
+            });
+        }
+        if values.len() > uniform.size as usize {
+            return Err(ShaderError::Uniform {
+                name: name.to_string(),
+                message: format!("the uniform has {} elements, but was given {}", uniform.size, values.len()),
+            });
+        }
+        T::upload(self.program.id(), uniform.location, values);
+        Ok(())
     }
 
     pub unsafe fn activate(&self) {
-        gl::UseProgram(self.program_id);
+        gl::UseProgram(self.program.id());
+    }
+
+    // Rebuild the program if any of the files it was built from changed on disk. Returns None if nothing
+    // changed. Call this from the render thread, and activate the shader again after a successful reload.
+    pub unsafe fn reload_if_changed<F>(&mut self, check: F) -> Option<Result<(), ShaderError>>
+        where F: Fn(&Shader) -> Result<(), ShaderError>
+    {
+        let changed = self.watched_files.iter().any(|(path, time)| modified_time(path) != *time);
+        if !changed {
+            return None;
+        }
+        // Remember the new times even if the reload fails, so a broken file isn't rebuilt every frame
+        for (path, time) in self.watched_files.iter_mut() {
+            *time = modified_time(path);
+        }
+        Some(self.reload(check))
+    }
+
+    // Rebuild the program from its source files. The new program only replaces the old one if it builds
+    // and `check` accepts it, otherwise the old program is kept.
+    pub unsafe fn reload<F>(&mut self, check: F) -> Result<(), ShaderError>
+        where F: Fn(&Shader) -> Result<(), ShaderError>
+    {
+        let mut builder = ShaderBuilder::new();
+        for (name, value) in &self.defines {
+            builder = builder.define(name, value);
+        }
+        if let Some(cache_dir) = &self.cache_dir {
+            builder = builder.cache_dir(cache_dir);
+        }
+        for path in &self.source_paths {
+            builder = builder.attach_file(path)?;
+        }
+        let shader = builder.link()?;
+        check(&shader)?;
+
+        // The old program is deleted as it's replaced
+        self.program = shader.program;
+        self.watched_files = shader.watched_files;
+        self.reflection = shader.reflection;
+        self.uniforms = shader.uniforms;
+        self.missing_uniforms = shader.missing_uniforms;
+        Ok(())
     }
 }
 
-impl Into<gl::types::GLenum> for ShaderType {
-    fn into(self) -> gl::types::GLenum {
-        match self {
+impl From<ShaderType> for gl::types::GLenum {
+    fn from(shader_type: ShaderType) -> gl::types::GLenum {
+        match shader_type {
             ShaderType::Vertex                  => { gl::VERTEX_SHADER          },
             ShaderType::Fragment                => { gl::FRAGMENT_SHADER        },
             ShaderType::TessellationControl     => { gl::TESS_CONTROL_SHADER    },
             ShaderType::TessellationEvaluation  => { gl::TESS_EVALUATION_SHADER } ,
             ShaderType::Geometry                => { gl::GEOMETRY_SHADER        },
+            ShaderType::Compute                 => { gl::COMPUTE_SHADER         },
         }
     }
 }
@@ -56,6 +361,7 @@ impl ShaderType {
             "tcs"  => { Ok(ShaderType::TessellationControl) },
             "tes"  => { Ok(ShaderType::TessellationEvaluation) },
             "geom" => { Ok(ShaderType::Geometry) },
+            "comp" => { Ok(ShaderType::Compute) },
             e => { Err(e.to_string()) },
         }
     }
@@ -64,91 +370,212 @@ impl ShaderType {
 impl ShaderBuilder {
     pub unsafe fn new() -> ShaderBuilder {
         ShaderBuilder {
-            program_id: gl::CreateProgram(),
+            program: Program::new(),
             shaders: vec![],
+            sources: vec![],
+            source_paths: vec![],
+            watched_files: vec![],
+            defines: vec![],
+            cache_dir: None,
         }
     }
 
-    pub unsafe fn attach_file(self, shader_path: &str) -> ShaderBuilder {
-        let path = Path::new(shader_path);
-        if let Some(extension) = path.extension() {
-            let shader_type = ShaderType::from_ext(extension)
-                .expect("Failed to parse file extension.");
-            let shader_src = std::fs::read_to_string(path)
-                .expect(&format!("Failed to read shader source. {}", shader_path));
-            self.compile_shader(&shader_src, shader_type)
-        } else {
-            panic!("Failed to read extension of file with path: {}", shader_path);
-        }
+    // Cache linked program binaries in this directory, to skip compiling on later runs
+    pub fn cache_dir<P: AsRef<Path>>(mut self, dir: P) -> ShaderBuilder {
+        self.cache_dir = Some(dir.as_ref().to_path_buf());
+        self
     }
 
-    pub unsafe fn compile_shader(mut self, shader_src: &str, shader_type: ShaderType) -> ShaderBuilder {
-        let shader = gl::CreateShader(shader_type.into());
-        let c_str_shader = CString::new(shader_src.as_bytes()).unwrap();
-        gl::ShaderSource(shader, 1, &c_str_shader.as_ptr(), ptr::null());
-        gl::CompileShader(shader);
+    // Add a `#define name value` to every file attached after this
+    pub fn define(mut self, name: &str, value: &str) -> ShaderBuilder {
+        self.defines.push((name.to_string(), value.to_string()));
+        self
+    }
 
-        if !self.check_shader_errors(shader) {
-            panic!("Shader failed to compile.");
+    pub unsafe fn attach_file(mut self, shader_path: &str) -> Result<ShaderBuilder, ShaderError> {
+        let path = Path::new(shader_path);
+        // Read the time before the contents, so an edit made in between is picked up by the next reload
+        self.source_paths.push(shader_path.to_string());
+        self.watched_files.push((shader_path.to_string(), modified_time(shader_path)));
+        let shader_type = match path.extension().map(ShaderType::from_ext) {
+            Some(Ok(shader_type)) => shader_type,
+            _ => return Err(ShaderError::UnknownExtension(shader_path.to_string())),
+        };
+        let source = preprocess(shader_path, &self.defines)?;
+        for include in source.files.iter().skip(1) {
+            self.watched_files.push((include.clone(), modified_time(include)));
         }
+        self.sources.push(PendingSource { source: source.source, shader_type: shader_type.into(), files: source.files });
+        Ok(self)
+    }
 
-        self.shaders.push(shader);
+    #[allow(dead_code)]
+    pub unsafe fn compile_shader(mut self, shader_src: &str, shader_type: ShaderType) -> Result<ShaderBuilder, ShaderError> {
+        self.sources.push(PendingSource {
+            source: shader_src.to_string(),
+            shader_type: shader_type.into(),
+            files: vec!["<source>".to_string()],
+        });
+        Ok(self)
+    }
 
-        self
+    unsafe fn compile_sources(&mut self) -> Result<(), ShaderError> {
+        for pending in &self.sources {
+            let shader = ShaderObject::new(pending.shader_type);
+            let c_str_shader = CString::new(pending.source.as_bytes()).unwrap();
+            gl::ShaderSource(shader.id(), 1, &c_str_shader.as_ptr(), ptr::null());
+            gl::CompileShader(shader.id());
+            self.check_shader_errors(shader.id(), &pending.files)?;
+            self.shaders.push(shader);
+        }
+        Ok(())
     }
 
-    unsafe fn check_shader_errors(&self, shader_id: u32) -> bool {
+    unsafe fn check_shader_errors(&self, shader_id: u32, files: &[String]) -> Result<(), ShaderError> {
         let mut success = i32::from(gl::FALSE);
-        let mut info_log = Vec::with_capacity(512);
-        info_log.set_len(512 - 1);
         gl::GetShaderiv(shader_id, gl::COMPILE_STATUS, &mut success);
         if success != i32::from(gl::TRUE) {
-            gl::GetShaderInfoLog(
-                shader_id,
-                512,
-                ptr::null_mut(),
-                info_log.as_mut_ptr() as *mut gl::types::GLchar,
-            );
-            println!("ERROR::Shader Compilation Failed!\n{}", String::from_utf8_lossy(&info_log));
-            return false;
+            let log = shader_info_log(shader_id);
+            return Err(ShaderError::Compile {
+                path: files.first().cloned().unwrap_or_default(),
+                entries: parse_info_log(&log, files),
+                log,
+            });
         }
-        true
+        Ok(())
     }
 
-    unsafe fn check_linker_errors(&self) -> bool {
+    unsafe fn check_linker_errors(&self) -> Result<(), ShaderError> {
         let mut success = i32::from(gl::FALSE);
-        let mut info_log = Vec::with_capacity(512);
-        info_log.set_len(512 - 1);
-        gl::GetProgramiv(self.program_id, gl::LINK_STATUS, &mut success);
+        gl::GetProgramiv(self.program.id(), gl::LINK_STATUS, &mut success);
         if success != i32::from(gl::TRUE) {
-            gl::GetProgramInfoLog(
-                self.program_id,
-                512,
-                ptr::null_mut(),
-                info_log.as_mut_ptr() as *mut gl::types::GLchar,
-            );
-            println!("ERROR::SHADER::PROGRAM::COMPILATION_FAILED\n{}", String::from_utf8_lossy(&info_log));
-            return false;
+            return Err(ShaderError::Link { log: program_info_log(self.program.id()) });
         }
-        true
+        Ok(())
     }
 
-    #[must_use = "The shader program is useless if not stored in a variable."]
-    pub unsafe fn link(self) -> Shader {
-        for &shader in &self.shaders {
-            gl::AttachShader(self.program_id, shader);
+    // Only done in debug builds, as it asks the driver for a full check of the program
+    unsafe fn check_validation_errors(&self) -> Result<(), ShaderError> {
+        if !cfg!(debug_assertions) {
+            return Ok(());
+        }
+        let mut success = i32::from(gl::FALSE);
+        gl::ValidateProgram(self.program.id());
+        gl::GetProgramiv(self.program.id(), gl::VALIDATE_STATUS, &mut success);
+        if success != i32::from(gl::TRUE) {
+            return Err(ShaderError::Validate { log: program_info_log(self.program.id()) });
         }
-        gl::LinkProgram(self.program_id);
+        Ok(())
+    }
 
-        // todo:: use this to make safer abstraction
-        self.check_linker_errors();
+    unsafe fn compile_and_link(&mut self) -> Result<(), ShaderError> {
+        self.compile_sources()?;
+        for shader in &self.shaders {
+            gl::AttachShader(self.program.id(), shader.id());
+        }
+        gl::ProgramParameteri(self.program.id(), gl::PROGRAM_BINARY_RETRIEVABLE_HINT, gl::TRUE as i32);
+        gl::LinkProgram(self.program.id());
+        self.check_linker_errors()?;
 
-        for &shader in &self.shaders {
-            gl::DeleteShader(shader);
+        // The shaders are deleted as they're dropped
+        for shader in self.shaders.drain(..) {
+            gl::DetachShader(self.program.id(), shader.id());
         }
+        Ok(())
+    }
+
+    #[must_use = "The shader program is useless if not stored in a variable."]
+    pub unsafe fn link(mut self) -> Result<Shader, ShaderError> {
+        let cache = match &self.cache_dir {
+            Some(dir) if program_cache::is_supported() => {
+                let sources: Vec<_> = self.sources.iter()
+                    .map(|pending| (pending.shader_type, pending.files[0].as_str(), pending.source.as_str()))
+                    .collect();
+                Some((dir.clone(), program_cache::cache_key(&sources, &self.defines)))
+            },
+            _ => None,
+        };
+
+        let loaded_from_cache = match &cache {
+            Some((dir, key)) => program_cache::load(dir, key, self.program.id()),
+            None => false,
+        };
 
-        Shader {
-            program_id: self.program_id
+        if !loaded_from_cache {
+            self.compile_and_link()?;
+            if let Some((dir, key)) = &cache {
+                program_cache::store(dir, key, self.program.id());
+            }
         }
+
+        self.check_validation_errors()?;
+
+        let reflection = reflect_program(self.program.id());
+
+        Ok(Shader {
+            program: self.program,
+            source_paths: self.source_paths,
+            watched_files: self.watched_files,
+            defines: self.defines,
+            cache_dir: self.cache_dir,
+            uniforms: reflection.uniform_locations(),
+            reflection,
+            missing_uniforms: RefCell::new(HashSet::new()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files() -> Vec<String> {
+        vec!["shaders/simple.vert".to_string(), "shaders/lighting.glsl".to_string()]
+    }
+
+    // The entry a single line of log parses into, as (path, line, severity, message)
+    fn parse(line: &str) -> (String, Option<u32>, Severity, String) {
+        let entries = parse_info_log(line, &files());
+        assert_eq!(entries.len(), 1, "{:?}", entries);
+        let entry = entries.into_iter().next().unwrap();
+        (entry.path, entry.line, entry.severity, entry.message)
+    }
+
+    #[test]
+    fn parses_mesa_logs() {
+        assert_eq!(parse("0:12(5): error: `foo' undeclared"),
+            ("shaders/simple.vert".to_string(), Some(12), Severity::Error, "`foo' undeclared".to_string()));
+        assert_eq!(parse("1:3(10): warning: `bar' used uninitialized"),
+            ("shaders/lighting.glsl".to_string(), Some(3), Severity::Warning, "`bar' used uninitialized".to_string()));
+    }
+
+    #[test]
+    fn parses_nvidia_logs() {
+        assert_eq!(parse("1(27) : error C1008: undefined variable \"foo\""),
+            ("shaders/lighting.glsl".to_string(), Some(27), Severity::Error, "undefined variable \"foo\"".to_string()));
+    }
+
+    #[test]
+    fn parses_amd_and_intel_logs() {
+        assert_eq!(parse("ERROR: 0:8: 'foo' : undeclared identifier"),
+            ("shaders/simple.vert".to_string(), Some(8), Severity::Error, "'foo' : undeclared identifier".to_string()));
+        assert_eq!(parse("WARNING: 0:2: extension not supported"),
+            ("shaders/simple.vert".to_string(), Some(2), Severity::Warning, "extension not supported".to_string()));
+    }
+
+    #[test]
+    fn keeps_lines_it_cannot_place() {
+        // Counted as an error in the first file, without a line
+        assert_eq!(parse("Internal compiler error"),
+            ("shaders/simple.vert".to_string(), None, Severity::Error, "Internal compiler error".to_string()));
+        // A source string with no file is named by its number
+        assert_eq!(parse("4:1(1): error: syntax error").0, "<source 4>");
+    }
+
+    #[test]
+    fn parses_every_line_and_skips_blank_ones() {
+        let log = "0:1(1): error: first\n\n   \n0:2(1): error: second\n";
+        let lines: Vec<Option<u32>> = parse_info_log(log, &files()).iter().map(|entry| entry.line).collect();
+        assert_eq!(lines, vec![Some(1), Some(2)]);
     }
 }
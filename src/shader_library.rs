@@ -0,0 +1,232 @@
+use std::{
+    collections::HashMap,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
+
+use crate::shader::{Shader, ShaderBuilder, ShaderError};
+
+// Extra checks run on a variant once it is built
+pub type Validator = fn(&Shader) -> Result<(), ShaderError>;
+
+// The value a material picks for a shader option
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OptionValue {
+    Bool(bool),
+    Int(i32),
+}
+
+enum OptionKind {
+    Bool,
+    Int(RangeInclusive<i32>),
+}
+
+struct ShaderOption {
+    name    : String,
+    kind    : OptionKind,
+    default : i32,
+}
+
+// Which shader features a node is drawn with. Options left out use the library's defaults.
+#[derive(Clone, Default, Debug)]
+pub struct Material {
+    pub options: Vec<(String, OptionValue)>,
+}
+
+// Compiles variants of one set of shader sources, one per combination of option values, with each
+// option injected as a define. Booleans are defined as 1 or 0, so the sources test them with `#if`.
+// Variants are compiled the first time a material asks for them and kept around after that.
+pub struct ShaderLibrary {
+    paths     : Vec<String>,
+    options   : Vec<ShaderOption>,
+    cache_dir : Option<PathBuf>,
+    validator : Option<Validator>,
+    variants  : HashMap<Vec<i32>, Shader>, // Keyed on the value of every option, in declaration order
+}
+
+#[allow(dead_code)]
+impl Material {
+    pub fn with_flag(mut self, name: &str, value: bool) -> Material {
+        self.set(name, OptionValue::Bool(value));
+        self
+    }
+
+    pub fn with_int(mut self, name: &str, value: i32) -> Material {
+        self.set(name, OptionValue::Int(value));
+        self
+    }
+
+    pub fn set(&mut self, name: &str, value: OptionValue) {
+        match self.options.iter_mut().find(|(option, _)| option == name) {
+            Some((_, old)) => *old = value,
+            None => self.options.push((name.to_string(), value)),
+        }
+    }
+}
+
+impl ShaderLibrary {
+    pub fn new(paths: &[&str]) -> ShaderLibrary {
+        ShaderLibrary {
+            paths     : paths.iter().map(|path| path.to_string()).collect(),
+            options   : vec![],
+            cache_dir : None,
+            validator : None,
+            variants  : HashMap::new(),
+        }
+    }
+
+    pub fn bool_option(mut self, name: &str, default: bool) -> ShaderLibrary {
+        self.options.push(ShaderOption { name: name.to_string(), kind: OptionKind::Bool, default: default as i32 });
+        self
+    }
+
+    pub fn int_option(mut self, name: &str, default: i32, range: RangeInclusive<i32>) -> ShaderLibrary {
+        assert!(range.contains(&default), "Default of shader option {} is outside {:?}", name, range);
+        self.options.push(ShaderOption { name: name.to_string(), kind: OptionKind::Int(range), default });
+        self
+    }
+
+    pub fn cache_dir<P: AsRef<Path>>(mut self, dir: P) -> ShaderLibrary {
+        self.cache_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    // Run on every variant after it has been compiled or reloaded, e.g. to check uniform block layouts
+    pub fn validate(mut self, validator: Validator) -> ShaderLibrary {
+        self.validator = Some(validator);
+        self
+    }
+
+    // The value of every option for this material, in declaration order
+    fn variant_key(&self, material: &Material) -> Result<Vec<i32>, ShaderError> {
+        for (name, _) in &material.options {
+            if !self.options.iter().any(|option| &option.name == name) {
+                return Err(ShaderError::Feature { name: name.clone(), message: "No such shader option".to_string() });
+            }
+        }
+
+        self.options.iter().map(|option| {
+            let value = match material.options.iter().find(|(name, _)| name == &option.name) {
+                Some((_, value)) => value,
+                None => return Ok(option.default),
+            };
+            match (&option.kind, value) {
+                (OptionKind::Bool, OptionValue::Bool(value)) => Ok(*value as i32),
+                (OptionKind::Int(range), OptionValue::Int(value)) if range.contains(value) => Ok(*value),
+                (OptionKind::Int(range), OptionValue::Int(value)) => Err(ShaderError::Feature {
+                    name: option.name.clone(),
+                    message: format!("{} is outside {:?}", value, range),
+                }),
+                (OptionKind::Bool, _) => Err(ShaderError::Feature { name: option.name.clone(), message: "Expected a bool".to_string() }),
+                (OptionKind::Int(_), _) => Err(ShaderError::Feature { name: option.name.clone(), message: "Expected an int".to_string() }),
+            }
+        }).collect()
+    }
+
+    unsafe fn compile(&self, key: &[i32]) -> Result<Shader, ShaderError> {
+        let mut builder = ShaderBuilder::new();
+        for (option, value) in self.options.iter().zip(key) {
+            builder = builder.define(&option.name, &value.to_string());
+        }
+        if let Some(cache_dir) = &self.cache_dir {
+            builder = builder.cache_dir(cache_dir);
+        }
+        for path in &self.paths {
+            builder = builder.attach_file(path)?;
+        }
+        let shader = builder.link()?;
+        if let Some(validator) = self.validator {
+            validator(&shader)?;
+        }
+        Ok(shader)
+    }
+
+    // The variant for this material, compiled on first use
+    pub unsafe fn get(&mut self, material: &Material) -> Result<&Shader, ShaderError> {
+        let key = self.variant_key(material)?;
+        if !self.variants.contains_key(&key) {
+            let shader = self.compile(&key)?;
+            self.variants.insert(key.clone(), shader);
+        }
+        Ok(&self.variants[&key])
+    }
+
+    // Reload every variant whose sources have changed. Returns None if nothing changed, otherwise
+    // the first error, if any. Variants that fail keep their old program.
+    pub unsafe fn reload_if_changed(&mut self) -> Option<Result<(), ShaderError>> {
+        let mut result = None;
+        let validator = self.validator;
+        for shader in self.variants.values_mut() {
+            // Validated before it is swapped in, so a variant that fails the checks keeps its old program too
+            let reloaded = match shader.reload_if_changed(|shader| validator.map_or(Ok(()), |validator| validator(shader))) {
+                Some(reloaded) => reloaded,
+                None => continue,
+            };
+            match (&result, reloaded) {
+                (None, reloaded) | (Some(Ok(())), reloaded @ Err(_)) => result = Some(reloaded),
+                _ => { },
+            }
+        }
+        result
+    }
+
+    #[allow(dead_code)]
+    pub fn variant_count(&self) -> usize {
+        self.variants.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn library() -> ShaderLibrary {
+        ShaderLibrary::new(&[])
+            .bool_option("LIT", true)
+            .bool_option("FOG", false)
+            .int_option("DEBUG_VIEW", 0, 0..=2)
+    }
+
+    fn error_message(result: Result<Vec<i32>, ShaderError>) -> (String, String) {
+        match result {
+            Err(ShaderError::Feature { name, message }) => (name, message),
+            other => panic!("Expected a feature error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn options_left_out_use_the_defaults() {
+        assert_eq!(library().variant_key(&Material::default()).unwrap(), vec![1, 0, 0]);
+        let material = Material::default().with_int("DEBUG_VIEW", 2).with_flag("FOG", true);
+        assert_eq!(library().variant_key(&material).unwrap(), vec![1, 1, 2]);
+    }
+
+    #[test]
+    fn later_values_replace_earlier_ones() {
+        let material = Material::default().with_flag("LIT", false).with_flag("LIT", true);
+        assert_eq!(material.options.len(), 1);
+        assert_eq!(library().variant_key(&material).unwrap(), vec![1, 0, 0]);
+    }
+
+    #[test]
+    fn rejects_ints_outside_the_range() {
+        let (name, message) = error_message(library().variant_key(&Material::default().with_int("DEBUG_VIEW", 3)));
+        assert_eq!((name.as_str(), message.as_str()), ("DEBUG_VIEW", "3 is outside 0..=2"));
+        assert!(library().variant_key(&Material::default().with_int("DEBUG_VIEW", -1)).is_err());
+    }
+
+    #[test]
+    fn rejects_values_of_the_wrong_type() {
+        let (name, message) = error_message(library().variant_key(&Material::default().with_int("LIT", 1)));
+        assert_eq!((name.as_str(), message.as_str()), ("LIT", "Expected a bool"));
+        let (name, message) = error_message(library().variant_key(&Material::default().with_flag("DEBUG_VIEW", true)));
+        assert_eq!((name.as_str(), message.as_str()), ("DEBUG_VIEW", "Expected an int"));
+    }
+
+    #[test]
+    fn rejects_unknown_options() {
+        let (name, message) = error_message(library().variant_key(&Material::default().with_flag("SHADOWS", true)));
+        assert_eq!((name.as_str(), message.as_str()), ("SHADOWS", "No such shader option"));
+    }
+}
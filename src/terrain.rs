@@ -0,0 +1,412 @@
+extern crate nalgebra_glm as glm;
+use std::f32::consts::PI;
+
+use crate::mesh::Mesh;
+
+// A regular grid of heights in the range [0, 1], stored row by row (x varies fastest)
+pub struct Heightmap {
+    pub width   : usize,
+    pub depth   : usize,
+    pub heights : Vec<f32>,
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Copy)]
+pub enum NoiseKind {
+    Fbm,    // Smooth rolling hills
+    Ridged, // Sharp crests, good for mountain ranges and crater rims
+}
+
+#[derive(Clone, Copy)]
+pub struct NoiseParams {
+    pub kind        : NoiseKind,
+    pub seed        : u32,
+    pub octaves     : u32,
+    pub frequency   : f32, // Frequency of the first octave, in cycles per grid cell
+    pub lacunarity  : f32, // Frequency multiplier between octaves
+    pub gain        : f32, // Amplitude multiplier between octaves
+}
+
+#[derive(Clone)]
+pub struct TerrainParams {
+    pub cell_size    : f32,                // Distance between neighbouring grid points
+    pub height_scale : f32,                // World height of a heightmap value of 1.0
+    pub skirt_depth  : f32,                // How far the skirts hang below the edges, 0.0 disables them
+    pub color_ramp   : Vec<(f32, [f32; 4])>, // Height -> color stops, sorted by height
+}
+
+impl Default for NoiseParams {
+    fn default() -> Self {
+        NoiseParams {
+            kind       : NoiseKind::Fbm,
+            seed       : 0,
+            octaves    : 6,
+            frequency  : 1.0 / 64.0,
+            lacunarity : 2.0,
+            gain       : 0.5,
+        }
+    }
+}
+
+impl Default for TerrainParams {
+    fn default() -> Self {
+        TerrainParams {
+            cell_size    : 1.0,
+            height_scale : 20.0,
+            skirt_depth  : 0.0,
+            color_ramp   : vec![
+                (0.0, [0.25, 0.25, 0.27, 1.0]),
+                (0.5, [0.55, 0.53, 0.50, 1.0]),
+                (1.0, [0.90, 0.90, 0.88, 1.0]),
+            ],
+        }
+    }
+}
+
+// == // Noise
+
+// Integer hash of a lattice point, used to pick gradients
+fn hash(x: i32, z: i32, seed: u32) -> u32 {
+    let mut h = seed.wrapping_mul(0x27d4_eb2d)
+        ^ (x as u32).wrapping_mul(0x85eb_ca6b)
+        ^ (z as u32).wrapping_mul(0xc2b2_ae35);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2c1b_3c6d);
+    h ^= h >> 12;
+    h = h.wrapping_mul(0x297a_2d39);
+    h ^= h >> 15;
+    h
+}
+
+fn gradient_dot(ix: i32, iz: i32, dx: f32, dz: f32, seed: u32) -> f32 {
+    let angle = (hash(ix, iz, seed) & 0xffff) as f32 / 65536.0 * 2.0 * PI;
+    angle.cos() * dx + angle.sin() * dz
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+// 2D gradient noise in roughly [-1, 1]
+pub fn gradient_noise(x: f32, z: f32, seed: u32) -> f32 {
+    let x0 = x.floor();
+    let z0 = z.floor();
+    let (ix, iz) = (x0 as i32, z0 as i32);
+    let (fx, fz) = (x - x0, z - z0);
+
+    let n00 = gradient_dot(ix,     iz,     fx,       fz,       seed);
+    let n10 = gradient_dot(ix + 1, iz,     fx - 1.0, fz,       seed);
+    let n01 = gradient_dot(ix,     iz + 1, fx,       fz - 1.0, seed);
+    let n11 = gradient_dot(ix + 1, iz + 1, fx - 1.0, fz - 1.0, seed);
+
+    let (u, v) = (fade(fx), fade(fz));
+    let nx0 = n00 + u * (n10 - n00);
+    let nx1 = n01 + u * (n11 - n01);
+    (nx0 + v * (nx1 - nx0)) * std::f32::consts::SQRT_2
+}
+
+impl NoiseParams {
+    // Sample the noise at the given grid coordinates. The result is in [0, 1]
+    pub fn sample(&self, x: f32, z: f32) -> f32 {
+        let mut frequency = self.frequency;
+        let mut amplitude = 1.0;
+        let mut total = 0.0;
+        let mut amplitude_sum = 0.0;
+        // Ridged noise lets each octave be damped by the one before it, so detail gathers on the crests
+        let mut weight = 1.0;
+
+        for octave in 0..self.octaves {
+            let n = gradient_noise(x * frequency, z * frequency, self.seed.wrapping_add(octave));
+            match self.kind {
+                NoiseKind::Fbm => {
+                    total += amplitude * (n * 0.5 + 0.5);
+                },
+                NoiseKind::Ridged => {
+                    let ridge = (1.0 - n.abs()).powi(2) * weight;
+                    weight = ridge.clamp(0.0, 1.0);
+                    total += amplitude * ridge;
+                },
+            }
+            amplitude_sum += amplitude;
+            frequency *= self.lacunarity;
+            amplitude *= self.gain;
+        }
+
+        if amplitude_sum > 0.0 {
+            (total / amplitude_sum).clamp(0.0, 1.0)
+        } else {
+            0.0
+        }
+    }
+}
+
+// == // Heightmaps
+
+impl Heightmap {
+    // Load a grayscale image, where black is the lowest point and white the highest.
+    // Colored images are converted to luminance first.
+    pub fn from_image(path: &str) -> Heightmap {
+        let image = image::open(path)
+            .unwrap_or_else(|e| panic!("Failed to load heightmap {}: {}", path, e))
+            .to_luma16();
+        let (width, depth) = image.dimensions();
+        Heightmap {
+            width   : width as usize,
+            depth   : depth as usize,
+            heights : image.pixels().map(|p| p.0[0] as f32 / u16::MAX as f32).collect(),
+        }
+    }
+
+    // Fill a grid of the given size by sampling a noise function at every grid point
+    pub fn from_noise(width: usize, depth: usize, noise: &NoiseParams) -> Heightmap {
+        Heightmap::from_fn(width, depth, |x, z| noise.sample(x as f32, z as f32))
+    }
+
+    pub fn from_fn<F: Fn(usize, usize) -> f32>(width: usize, depth: usize, f: F) -> Heightmap {
+        let mut heights = Vec::with_capacity(width * depth);
+        for z in 0..depth {
+            for x in 0..width {
+                heights.push(f(x, z));
+            }
+        }
+        Heightmap { width, depth, heights }
+    }
+
+    // Height at a grid point, clamped to the edges of the map
+    pub fn get(&self, x: isize, z: isize) -> f32 {
+        let x = x.clamp(0, self.width as isize - 1) as usize;
+        let z = z.clamp(0, self.depth as isize - 1) as usize;
+        self.heights[z * self.width + x]
+    }
+
+    // Bilinearly interpolated height at fractional grid coordinates
+    pub fn sample(&self, x: f32, z: f32) -> f32 {
+        let (x0, z0) = (x.floor(), z.floor());
+        let (fx, fz) = (x - x0, z - z0);
+        let (ix, iz) = (x0 as isize, z0 as isize);
+        let h0 = self.get(ix, iz)     * (1.0 - fx) + self.get(ix + 1, iz)     * fx;
+        let h1 = self.get(ix, iz + 1) * (1.0 - fx) + self.get(ix + 1, iz + 1) * fx;
+        h0 * (1.0 - fz) + h1 * fz
+    }
+
+    // Triangulate the heightmap into a mesh centered on the origin in the xz-plane
+    pub fn to_mesh(&self, params: &TerrainParams) -> Mesh {
+        self.region_to_mesh(0, 0, self.width, self.depth, params)
+    }
+
+    // Triangulate a w x d window of the heightmap starting at grid point (x0, z0), centered on the origin.
+    // Points just outside the window are still used for the normals, so neighbouring windows shade seamlessly.
+    pub fn region_to_mesh(&self, x0: usize, z0: usize, w: usize, d: usize, params: &TerrainParams) -> Mesh {
+        assert!(w >= 2 && d >= 2, "A heightmap needs at least 2x2 points to form a mesh");
+        assert!(x0 + w <= self.width && z0 + d <= self.depth, "Region is outside of the heightmap");
+
+        let origin = glm::vec2(
+            -((w - 1) as f32) * params.cell_size / 2.0,
+            -((d - 1) as f32) * params.cell_size / 2.0,
+        );
+
+        let mut vertices = Vec::with_capacity(w * d * 3);
+        let mut normals  = Vec::with_capacity(w * d * 3);
+        let mut uvs      = Vec::with_capacity(w * d * 2);
+        let mut colors   = Vec::with_capacity(w * d * 4);
+        let mut indices  = Vec::with_capacity((w - 1) * (d - 1) * 6);
+
+        for z in 0..d {
+            for x in 0..w {
+                let (xi, zi) = ((x0 + x) as isize, (z0 + z) as isize);
+                let h = self.get(xi, zi);
+                vertices.extend_from_slice(&[
+                    origin.x + x as f32 * params.cell_size,
+                    h * params.height_scale,
+                    origin.y + z as f32 * params.cell_size,
+                ]);
+
+                // Central differences give the slope in each direction
+                let dx = (self.get(xi + 1, zi) - self.get(xi - 1, zi)) * params.height_scale / (2.0 * params.cell_size);
+                let dz = (self.get(xi, zi + 1) - self.get(xi, zi - 1)) * params.height_scale / (2.0 * params.cell_size);
+                let normal = glm::normalize(&glm::vec3(-dx, 1.0, -dz));
+                normals.extend_from_slice(&[normal.x, normal.y, normal.z]);
+
+                uvs.extend_from_slice(&[x as f32 / (w - 1) as f32, z as f32 / (d - 1) as f32]);
+                colors.extend_from_slice(&ramp_color(&params.color_ramp, h));
+            }
+        }
+
+        for z in 0..(d - 1) as u32 {
+            for x in 0..(w - 1) as u32 {
+                let v00 = z * w as u32 + x;
+                let v10 = v00 + 1;
+                let v01 = v00 + w as u32;
+                let v11 = v01 + 1;
+                indices.extend_from_slice(&[v00, v01, v10, v10, v01, v11]);
+            }
+        }
+
+        let mut mesh = Mesh {
+            vertices,
+            normals,
+            uvs,
+            colors,
+            index_count: indices.len() as i32,
+            indices,
+        };
+
+        if params.skirt_depth > 0.0 {
+            // Walk the border counter-clockwise (seen from above) so the skirt walls face outwards
+            let mut border = vec![];
+            border.extend(0..w);
+            border.extend((1..d).map(|z| z * w + w - 1));
+            border.extend((0..w - 1).rev().map(|x| (d - 1) * w + x));
+            border.extend((0..d - 1).rev().map(|z| z * w));
+            add_skirt(&mut mesh, &border, params.skirt_depth);
+        }
+
+        mesh
+    }
+}
+
+// Hang a vertical strip below a closed loop of vertices, hiding gaps between neighbouring meshes
+pub fn add_skirt(mesh: &mut Mesh, border: &[usize], skirt_depth: f32) {
+    let first_skirt_vertex = mesh.vertices.len() / 3;
+    for &i in border {
+        mesh.vertices.extend_from_slice(&[mesh.vertices[3*i], mesh.vertices[3*i + 1] - skirt_depth, mesh.vertices[3*i + 2]]);
+        mesh.normals.extend_from_slice(&[mesh.normals[3*i], mesh.normals[3*i + 1], mesh.normals[3*i + 2]]);
+        if !mesh.uvs.is_empty() {
+            mesh.uvs.extend_from_slice(&[mesh.uvs[2*i], mesh.uvs[2*i + 1]]);
+        }
+        mesh.colors.extend_from_slice(&[mesh.colors[4*i], mesh.colors[4*i + 1], mesh.colors[4*i + 2], mesh.colors[4*i + 3]]);
+    }
+
+    for k in 0..border.len() {
+        let next = (k + 1) % border.len();
+        let (a, b) = (border[k] as u32, border[next] as u32);
+        let (a_low, b_low) = ((first_skirt_vertex + k) as u32, (first_skirt_vertex + next) as u32);
+        mesh.indices.extend_from_slice(&[a, b, a_low, b, b_low, a_low]);
+    }
+    mesh.index_count = mesh.indices.len() as i32;
+}
+
+// Linearly interpolate between the color stops surrounding the given height
+pub fn ramp_color(ramp: &[(f32, [f32; 4])], height: f32) -> [f32; 4] {
+    match ramp {
+        [] => [1.0, 1.0, 1.0, 1.0],
+        [(_, color)] => *color,
+        _ => {
+            if height <= ramp[0].0 {
+                return ramp[0].1;
+            }
+            for pair in ramp.windows(2) {
+                let ((h0, c0), (h1, c1)) = (pair[0], pair[1]);
+                if height <= h1 {
+                    let t = if h1 > h0 { (height - h0) / (h1 - h0) } else { 1.0 };
+                    return [
+                        c0[0] + (c1[0] - c0[0]) * t,
+                        c0[1] + (c1[1] - c0[1]) * t,
+                        c0[2] + (c1[2] - c0[2]) * t,
+                        c0[3] + (c1[3] - c0[3]) * t,
+                    ];
+                }
+            }
+            ramp[ramp.len() - 1].1
+        },
+    }
+}
+
+// == // Height queries
+
+// The highest point of a mesh within each cell of a grid over the xz-plane, for keeping things
+// above the mesh without searching its triangles
+pub struct HeightGrid {
+    origin    : glm::Vec2,
+    cell_size : f32,
+    width     : usize,
+    depth     : usize,
+    heights   : Vec<f32>, // NEG_INFINITY where no vertex fell into the cell
+}
+
+impl HeightGrid {
+    pub fn from_mesh(mesh: &Mesh, cell_size: f32) -> HeightGrid {
+        let (mut min, mut max) = (glm::vec2(f32::INFINITY, f32::INFINITY), glm::vec2(f32::NEG_INFINITY, f32::NEG_INFINITY));
+        for v in mesh.vertices.chunks(3) {
+            min = glm::min2(&min, &glm::vec2(v[0], v[2]));
+            max = glm::max2(&max, &glm::vec2(v[0], v[2]));
+        }
+        if mesh.vertices.is_empty() {
+            return HeightGrid { origin: glm::zero(), cell_size, width: 0, depth: 0, heights: vec![] };
+        }
+
+        let width = ((max.x - min.x) / cell_size) as usize + 1;
+        let depth = ((max.y - min.y) / cell_size) as usize + 1;
+        let mut heights = vec![f32::NEG_INFINITY; width * depth];
+        for v in mesh.vertices.chunks(3) {
+            let x = ((v[0] - min.x) / cell_size) as usize;
+            let z = ((v[2] - min.y) / cell_size) as usize;
+            let height = &mut heights[z * width + x];
+            *height = height.max(v[1]);
+        }
+        HeightGrid { origin: min, cell_size, width, depth, heights }
+    }
+
+    // The highest point in the cell containing (x, z) and the cells around it, or None outside the mesh.
+    // Including the neighbours covers triangles spanning cells none of their vertices are in.
+    pub fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        let cx = ((x - self.origin.x) / self.cell_size).floor() as isize;
+        let cz = ((z - self.origin.y) / self.cell_size).floor() as isize;
+        let mut highest = f32::NEG_INFINITY;
+        for nz in (cz - 1).max(0)..=(cz + 1).min(self.depth as isize - 1) {
+            for nx in (cx - 1).max(0)..=(cx + 1).min(self.width as isize - 1) {
+                highest = highest.max(self.heights[nz as usize * self.width + nx as usize]);
+            }
+        }
+        Some(highest).filter(|h| h.is_finite())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 5x5 points one apart from -2 to 2, rising by 2 per point along the x-axis from 0 at x = -2
+    fn ramp_grid() -> HeightGrid {
+        let mesh = Heightmap::from_fn(5, 5, |x, _| x as f32 * 0.1).to_mesh(&TerrainParams::default());
+        HeightGrid::from_mesh(&mesh, 1.0)
+    }
+
+    #[test]
+    fn height_inside_is_the_highest_point_around() {
+        let grid = ramp_grid();
+        // In the cell of the point at x = 0, so the highest neighbour is the one at x = 1
+        assert_eq!(grid.height_at(0.2, 0.0), Some(6.0));
+        assert_eq!(grid.height_at(-1.9, 1.5), Some(2.0));
+    }
+
+    #[test]
+    fn height_at_the_border_reaches_one_cell_out() {
+        let grid = ramp_grid();
+        assert_eq!(grid.height_at(2.0, 0.0), Some(8.0));
+        assert_eq!(grid.height_at(3.5, 0.0), Some(8.0));
+        assert_eq!(grid.height_at(-2.5, -2.5), Some(0.0));
+    }
+
+    #[test]
+    fn no_height_outside() {
+        let grid = ramp_grid();
+        assert_eq!(grid.height_at(5.5, 0.0), None);
+        assert_eq!(grid.height_at(0.0, -10.0), None);
+
+        let empty = Mesh { vertices: vec![], normals: vec![], uvs: vec![], colors: vec![], indices: vec![], index_count: 0 };
+        assert_eq!(HeightGrid::from_mesh(&empty, 1.0).height_at(0.0, 0.0), None);
+    }
+
+    #[test]
+    fn no_height_between_vertices_far_apart() {
+        let mesh = Mesh {
+            vertices: vec![0.0, 1.0, 0.0,  10.0, 2.0, 0.0,  0.0, 3.0, 10.0],
+            normals: vec![], uvs: vec![], colors: vec![], indices: vec![0, 1, 2], index_count: 3,
+        };
+        let grid = HeightGrid::from_mesh(&mesh, 1.0);
+        assert_eq!(grid.height_at(0.5, 0.5), Some(1.0));
+        assert_eq!(grid.height_at(5.5, 5.5), None);
+        assert_eq!(grid.height_at(9.5, 0.5), Some(2.0));
+    }
+}
@@ -0,0 +1,85 @@
+extern crate nalgebra_glm as glm;
+use gl::types::GLenum;
+
+use crate::reflection::is_opaque_type;
+
+// Values that can be uploaded to a uniform with `Shader::set_uniform` and `Shader::set_uniform_array`.
+// The uploads use glProgramUniform*, so the shader doesn't have to be active.
+pub trait UniformValue: Sized {
+    // Whether a uniform of the given type, as reflected from the program, can hold this value
+    fn accepts(gl_type: GLenum) -> bool;
+
+    // Upload the values to consecutive array elements, starting at `location`
+    unsafe fn upload(program_id: u32, location: i32, values: &[Self]);
+}
+
+impl UniformValue for f32 {
+    fn accepts(gl_type: GLenum) -> bool {
+        gl_type == gl::FLOAT
+    }
+
+    unsafe fn upload(program_id: u32, location: i32, values: &[Self]) {
+        gl::ProgramUniform1fv(program_id, location, values.len() as i32, values.as_ptr());
+    }
+}
+
+impl UniformValue for i32 {
+    // Also used for booleans and for the texture unit of samplers and images
+    fn accepts(gl_type: GLenum) -> bool {
+        gl_type == gl::INT || gl_type == gl::BOOL || is_opaque_type(gl_type)
+    }
+
+    unsafe fn upload(program_id: u32, location: i32, values: &[Self]) {
+        gl::ProgramUniform1iv(program_id, location, values.len() as i32, values.as_ptr());
+    }
+}
+
+impl UniformValue for glm::Vec2 {
+    fn accepts(gl_type: GLenum) -> bool {
+        gl_type == gl::FLOAT_VEC2
+    }
+
+    unsafe fn upload(program_id: u32, location: i32, values: &[Self]) {
+        gl::ProgramUniform2fv(program_id, location, values.len() as i32, values.as_ptr() as *const f32);
+    }
+}
+
+impl UniformValue for glm::Vec3 {
+    fn accepts(gl_type: GLenum) -> bool {
+        gl_type == gl::FLOAT_VEC3
+    }
+
+    unsafe fn upload(program_id: u32, location: i32, values: &[Self]) {
+        gl::ProgramUniform3fv(program_id, location, values.len() as i32, values.as_ptr() as *const f32);
+    }
+}
+
+impl UniformValue for glm::Vec4 {
+    fn accepts(gl_type: GLenum) -> bool {
+        gl_type == gl::FLOAT_VEC4
+    }
+
+    unsafe fn upload(program_id: u32, location: i32, values: &[Self]) {
+        gl::ProgramUniform4fv(program_id, location, values.len() as i32, values.as_ptr() as *const f32);
+    }
+}
+
+impl UniformValue for glm::Mat3 {
+    fn accepts(gl_type: GLenum) -> bool {
+        gl_type == gl::FLOAT_MAT3
+    }
+
+    unsafe fn upload(program_id: u32, location: i32, values: &[Self]) {
+        gl::ProgramUniformMatrix3fv(program_id, location, values.len() as i32, gl::FALSE, values.as_ptr() as *const f32);
+    }
+}
+
+impl UniformValue for glm::Mat4 {
+    fn accepts(gl_type: GLenum) -> bool {
+        gl_type == gl::FLOAT_MAT4
+    }
+
+    unsafe fn upload(program_id: u32, location: i32, values: &[Self]) {
+        gl::ProgramUniformMatrix4fv(program_id, location, values.len() as i32, gl::FALSE, values.as_ptr() as *const f32);
+    }
+}
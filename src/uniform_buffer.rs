@@ -0,0 +1,250 @@
+extern crate nalgebra_glm as glm;
+use std::{marker::PhantomData, mem};
+
+use crate::gl_object::Buffer;
+use crate::reflection::ProgramReflection;
+use crate::shader::ShaderError;
+
+// Uniform buffer binding points shared by every program, see shaders/uniforms.glsl
+pub const FRAME_BINDING  : u32 = 0;
+pub const LIGHTS_BINDING : u32 = 1;
+
+pub const MAX_LIGHTS: usize = 8;
+
+// A Rust struct mirroring a std140 uniform block in the shaders
+pub trait Std140Block: Copy {
+    const NAME: &'static str;
+    const BINDING: u32;
+    // The byte offset of every member, by the name reflection reports for it
+    fn member_offsets() -> Vec<(&'static str, usize)>;
+}
+
+// Updated once per frame: `Frame` in the shaders
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct FrameUniforms {
+    pub view            : glm::Mat4,
+    pub projection      : glm::Mat4,
+    pub camera_position : glm::Vec3,
+    pub time            : f32,       // Seconds since start, packed into the padding after camera_position
+}
+
+// A directional light. The w components are only padding.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Light {
+    pub direction : glm::Vec4, // The direction the light travels in
+    pub color     : glm::Vec4,
+}
+
+// `Lights` in the shaders
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct LightUniforms {
+    pub ambient     : glm::Vec4,
+    pub light_count : i32,
+    _padding        : [i32; 3],
+    pub lights      : [Light; MAX_LIGHTS],
+}
+
+impl Std140Block for FrameUniforms {
+    const NAME: &'static str = "Frame";
+    const BINDING: u32 = FRAME_BINDING;
+    fn member_offsets() -> Vec<(&'static str, usize)> {
+        vec![("view", 0), ("projection", 64), ("cameraPosition", 128), ("time", 140)]
+    }
+}
+
+impl Std140Block for LightUniforms {
+    const NAME: &'static str = "Lights";
+    const BINDING: u32 = LIGHTS_BINDING;
+    fn member_offsets() -> Vec<(&'static str, usize)> {
+        // The second light checks the array stride
+        vec![("ambient", 0), ("lightCount", 16), ("lights[0].direction", 32), ("lights[0].color", 48), ("lights[1].direction", 64)]
+    }
+}
+
+impl Light {
+    pub fn directional(direction: glm::Vec3, color: glm::Vec3) -> Light {
+        let direction = glm::normalize(&direction);
+        Light {
+            direction : glm::vec4(direction.x, direction.y, direction.z, 0.0),
+            color     : glm::vec4(color.x, color.y, color.z, 1.0),
+        }
+    }
+}
+
+impl LightUniforms {
+    pub fn new(ambient: glm::Vec3, lights: &[Light]) -> LightUniforms {
+        assert!(lights.len() <= MAX_LIGHTS, "At most {} lights are supported", MAX_LIGHTS);
+        let mut light_array = [Light { direction: glm::zero(), color: glm::zero() }; MAX_LIGHTS];
+        light_array[..lights.len()].copy_from_slice(lights);
+        LightUniforms {
+            ambient     : glm::vec4(ambient.x, ambient.y, ambient.z, 1.0),
+            light_count : lights.len() as i32,
+            _padding    : [0; 3],
+            lights      : light_array,
+        }
+    }
+}
+
+impl LightUniforms {
+    pub const PRESET_NAMES: &'static [&'static str] = &["noon", "dusk", "night"];
+
+    // Lighting by name, to switch between at runtime
+    pub fn preset(name: &str) -> Option<LightUniforms> {
+        let lights = match name {
+            "noon" => LightUniforms::default(),
+            "dusk" => LightUniforms::new(glm::vec3(0.10, 0.08, 0.15), &[
+                Light::directional(glm::vec3(1.0, -0.2, 0.3), glm::vec3(1.0, 0.6, 0.3)),
+            ]),
+            "night" => LightUniforms::new(glm::vec3(0.05, 0.05, 0.10), &[
+                Light::directional(glm::vec3(-0.3, -1.0, 0.2), glm::vec3(0.25, 0.3, 0.45)),
+                Light::directional(glm::vec3(0.6, -0.4, -0.7), glm::vec3(0.15, 0.15, 0.2)),
+            ]),
+            _ => return None,
+        };
+        Some(lights)
+    }
+}
+
+impl Default for LightUniforms {
+    // The light the shaders used to hard-code
+    fn default() -> Self {
+        LightUniforms::new(glm::zero(), &[Light::directional(glm::vec3(0.8, -0.5, 0.6), glm::vec3(1.0, 1.0, 1.0))])
+    }
+}
+
+// Check that a program declares the block the way the Rust struct lays it out. Programs that
+// don't use the block pass.
+pub fn check_layout<T: Std140Block>(reflection: &ProgramReflection) -> Result<(), ShaderError> {
+    let block = match reflection.uniform_block(T::NAME) {
+        Some(block) => block,
+        None => return Ok(()),
+    };
+    let error = |message: String| Err(ShaderError::Layout { block: T::NAME.to_string(), message });
+
+    if block.binding as u32 != T::BINDING {
+        return error(format!("the block is bound to {}, expected binding = {}", block.binding, T::BINDING));
+    }
+    if block.data_size as usize > mem::size_of::<T>() {
+        return error(format!("the block is {} bytes, but the Rust struct only {}", block.data_size, mem::size_of::<T>()));
+    }
+    for (name, offset) in T::member_offsets() {
+        if let Some(uniform) = reflection.uniform(name) {
+            if uniform.offset as usize != offset {
+                return error(format!("{} is at offset {}, expected {}", name, uniform.offset, offset));
+            }
+        }
+    }
+    Ok(())
+}
+
+// A uniform buffer bound to the fixed binding point of its block
+pub struct UniformBuffer<T: Std140Block> {
+    pub buffer : Buffer,
+    block      : PhantomData<T>,
+}
+
+impl<T: Std140Block> UniformBuffer<T> {
+    pub unsafe fn new(data: &T) -> UniformBuffer<T> {
+        let buffer = Buffer::new();
+        gl::BindBuffer(gl::UNIFORM_BUFFER, buffer.id());
+        gl::BufferData(gl::UNIFORM_BUFFER, mem::size_of::<T>() as isize, data as *const T as *const _, gl::DYNAMIC_DRAW);
+        gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+        gl::BindBufferBase(gl::UNIFORM_BUFFER, T::BINDING, buffer.id());
+        UniformBuffer { buffer, block: PhantomData }
+    }
+
+    pub unsafe fn update(&self, data: &T) {
+        gl::BindBuffer(gl::UNIFORM_BUFFER, self.buffer.id());
+        gl::BufferSubData(gl::UNIFORM_BUFFER, 0, mem::size_of::<T>() as isize, data as *const T as *const _);
+        gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reflection::{UniformBlockInfo, UniformInfo};
+
+    // Reflection for a program declaring `Lights` with members at these offsets
+    fn lights_reflection(binding: i32, offsets: &[(&str, i32)]) -> ProgramReflection {
+        ProgramReflection {
+            uniform_blocks: vec![UniformBlockInfo {
+                name      : "Lights".to_string(),
+                binding,
+                data_size : mem::size_of::<LightUniforms>() as i32,
+                members   : offsets.iter().map(|(name, _)| name.to_string()).collect(),
+            }],
+            uniforms: offsets.iter().map(|&(name, offset)| UniformInfo {
+                name        : name.to_string(),
+                location    : -1,
+                gl_type     : gl::FLOAT_VEC4,
+                size        : 1,
+                block_index : 0,
+                offset,
+            }).collect(),
+            ..Default::default()
+        }
+    }
+
+    const STD140_LIGHTS: [(&str, i32); 5] = [
+        ("ambient", 0), ("lightCount", 16), ("lights[0].direction", 32), ("lights[0].color", 48), ("lights[1].direction", 64),
+    ];
+
+    #[test]
+    fn rust_structs_follow_std140() {
+        let frame = FrameUniforms { view: glm::identity(), projection: glm::identity(), camera_position: glm::zero(), time: 0.0 };
+        let base = &frame as *const _ as usize;
+        assert_eq!(&frame.camera_position as *const _ as usize - base, 128);
+        assert_eq!(&frame.time as *const _ as usize - base, 140);
+
+        let lights = LightUniforms::default();
+        let base = &lights as *const _ as usize;
+        for (name, offset) in LightUniforms::member_offsets() {
+            let field = match name {
+                "ambient" => &lights.ambient as *const _ as usize,
+                "lightCount" => &lights.light_count as *const _ as usize,
+                "lights[0].direction" => &lights.lights[0].direction as *const _ as usize,
+                "lights[0].color" => &lights.lights[0].color as *const _ as usize,
+                "lights[1].direction" => &lights.lights[1].direction as *const _ as usize,
+                _ => panic!("No field for {}", name),
+            };
+            assert_eq!(field - base, offset, "{}", name);
+        }
+    }
+
+    #[test]
+    fn accepts_matching_blocks() {
+        check_layout::<LightUniforms>(&lights_reflection(LIGHTS_BINDING as i32, &STD140_LIGHTS)).unwrap();
+        check_layout::<LightUniforms>(&ProgramReflection::default()).unwrap();
+    }
+
+    fn layout_error(reflection: &ProgramReflection) -> String {
+        match check_layout::<LightUniforms>(reflection) {
+            Err(ShaderError::Layout { block, message }) if block == "Lights" => message,
+            other => panic!("Expected a layout error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_wrong_offsets_and_strides() {
+        let mut offsets = STD140_LIGHTS;
+        offsets[1].1 = 20;
+        assert_eq!(layout_error(&lights_reflection(LIGHTS_BINDING as i32, &offsets)), "lightCount is at offset 20, expected 16");
+
+        // Lights packed tighter than std140 would put them
+        let mut offsets = STD140_LIGHTS;
+        offsets[4].1 = 56;
+        assert_eq!(layout_error(&lights_reflection(LIGHTS_BINDING as i32, &offsets)), "lights[1].direction is at offset 56, expected 64");
+    }
+
+    #[test]
+    fn rejects_wrong_bindings_and_sizes() {
+        assert!(layout_error(&lights_reflection(FRAME_BINDING as i32, &STD140_LIGHTS)).contains("bound to 0"));
+        let mut reflection = lights_reflection(LIGHTS_BINDING as i32, &STD140_LIGHTS);
+        reflection.uniform_blocks[0].data_size += 16;
+        assert!(layout_error(&reflection).contains("but the Rust struct only"));
+    }
+}
@@ -1,4 +1,5 @@
 use std::ffi::CString;
+use std::str::FromStr;
 
 pub unsafe fn get_gl_string(name: gl::types::GLenum) -> String {
     std::ffi::CStr::from_ptr(gl::GetString(name) as *mut i8).to_string_lossy().to_string()
@@ -30,3 +31,12 @@ pub extern "system" fn debug_callback(
     }
 }
 
+
+// The value following `flag` on the command line, if it is there
+pub fn flag_value<T: FromStr>(args: &[String], flag: &str) -> Option<T> {
+    let value = args.iter().position(|arg| arg == flag).and_then(|i| args.get(i + 1))?;
+    match value.parse() {
+        Ok(value) => Some(value),
+        Err(_) => panic!("Invalid value for {}: {}", flag, value),
+    }
+}
@@ -0,0 +1,555 @@
+use std::{ops::Range, ptr};
+
+use crate::gl_object::{Buffer, VertexArray};
+use crate::mesh::Mesh;
+
+// Which part of a Mesh an attribute is read from
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Semantic {
+    Position, // 3 components
+    Color,    // 4 components
+    Normal,   // 3 components
+    TexCoord, // 2 components
+}
+
+// How the components of an attribute are stored in the buffer
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttributeType {
+    Float,
+    HalfFloat,
+    Byte,
+    UnsignedByte,
+    Short,
+    UnsignedShort,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct VertexAttribute {
+    pub semantic       : Semantic,
+    pub location       : u32,
+    pub components     : usize,
+    pub attribute_type : AttributeType,
+    pub normalized     : bool, // Integer types are mapped to [0, 1] or [-1, 1] instead of being converted as is
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Storage {
+    Interleaved, // All attributes in one buffer, one vertex after the other
+    Separate,    // One buffer per attribute
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexType {
+    U16, // Falls back to U32 for meshes with more than 65536 vertices
+    U32,
+}
+
+// How often the vertices are expected to change, see Vao::update
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BufferUsage {
+    Static,           // Uploaded once
+    Dynamic,          // Parts are changed now and then, uploaded with glBufferSubData
+    Streaming(usize), // Changed every frame. Persistently mapped ring buffer with this many copies of the vertices
+}
+
+// Describes how the vertices of a mesh are laid out on the GPU
+#[derive(Clone, Debug)]
+pub struct VertexLayout {
+    pub attributes : Vec<VertexAttribute>,
+    pub storage    : Storage,
+    pub index_type : IndexType,
+    pub usage      : BufferUsage,
+}
+
+// One vertex buffer and the attributes it holds, with their offsets within a vertex
+struct VertexStream {
+    buffer     : Buffer,
+    attributes : Vec<(VertexAttribute, usize)>,
+    stride     : usize,
+}
+
+// The mapped vertex buffers of a streaming VAO. Every update writes the next segment, waiting for
+// the GPU to be done with it first. Segments are one full copy of the vertices each.
+struct Ring {
+    mapped   : Vec<*mut u8>, // One per stream
+    segments : usize,
+    current  : usize,
+    fences   : Vec<gl::types::GLsync>,
+}
+
+// A vertex array together with the buffers it reads from. Dropping it deletes all of them.
+pub struct Vao {
+    array            : VertexArray,
+    streams          : Vec<VertexStream>,
+    #[allow(dead_code)]
+    index_buffer     : Buffer, // Only held on to, so it lives as long as the vertex array
+    ring             : Option<Ring>,
+    pub vertex_count : usize,
+    pub index_count  : i32,
+    pub index_type   : gl::types::GLenum, // What to pass to glDrawElements
+}
+
+impl Semantic {
+    fn source_components(self) -> usize {
+        match self {
+            Semantic::Position => 3,
+            Semantic::Color    => 4,
+            Semantic::Normal   => 3,
+            Semantic::TexCoord => 2,
+        }
+    }
+
+    fn source(self, mesh: &Mesh) -> &[f32] {
+        match self {
+            Semantic::Position => &mesh.vertices,
+            Semantic::Color    => &mesh.colors,
+            Semantic::Normal   => &mesh.normals,
+            Semantic::TexCoord => &mesh.uvs,
+        }
+    }
+}
+
+impl AttributeType {
+    fn gl_type(self) -> gl::types::GLenum {
+        match self {
+            AttributeType::Float         => gl::FLOAT,
+            AttributeType::HalfFloat     => gl::HALF_FLOAT,
+            AttributeType::Byte          => gl::BYTE,
+            AttributeType::UnsignedByte  => gl::UNSIGNED_BYTE,
+            AttributeType::Short         => gl::SHORT,
+            AttributeType::UnsignedShort => gl::UNSIGNED_SHORT,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            AttributeType::Float => 4,
+            AttributeType::HalfFloat | AttributeType::Short | AttributeType::UnsignedShort => 2,
+            AttributeType::Byte | AttributeType::UnsignedByte => 1,
+        }
+    }
+
+    // Append one component in this format
+    fn encode(self, value: f32, normalized: bool, out: &mut Vec<u8>) {
+        match (self, normalized) {
+            (AttributeType::Float, _)                => out.extend_from_slice(&value.to_ne_bytes()),
+            (AttributeType::HalfFloat, _)            => out.extend_from_slice(&f32_to_f16(value).to_ne_bytes()),
+            (AttributeType::Byte, true)              => out.push((value.clamp(-1.0, 1.0) * 127.0).round() as i8 as u8),
+            (AttributeType::Byte, false)             => out.push(value as i8 as u8),
+            (AttributeType::UnsignedByte, true)      => out.push((value.clamp(0.0, 1.0) * 255.0).round() as u8),
+            (AttributeType::UnsignedByte, false)     => out.push(value as u8),
+            (AttributeType::Short, true)             => out.extend_from_slice(&((value.clamp(-1.0, 1.0) * 32767.0).round() as i16).to_ne_bytes()),
+            (AttributeType::Short, false)            => out.extend_from_slice(&(value as i16).to_ne_bytes()),
+            (AttributeType::UnsignedShort, true)     => out.extend_from_slice(&((value.clamp(0.0, 1.0) * 65535.0).round() as u16).to_ne_bytes()),
+            (AttributeType::UnsignedShort, false)    => out.extend_from_slice(&(value as u16).to_ne_bytes()),
+        }
+    }
+}
+
+// Convert to IEEE 754 half precision, rounding to nearest even. Out of range values become infinity.
+fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x007f_ffff;
+
+    if exponent == 0xff {
+        // Infinity stays infinity, NaN stays NaN
+        return sign | 0x7c00 | if mantissa != 0 { 0x0200 } else { 0 };
+    }
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if exponent <= 0 {
+        // Subnormal, or too small to represent at all
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x0080_0000;
+        let shift = (14 - exponent) as u32;
+        let half = mantissa >> shift;
+        let remainder = mantissa & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        let round_up = remainder > halfway || (remainder == halfway && half & 1 == 1);
+        return sign | (half + round_up as u32) as u16;
+    }
+    let half = ((exponent as u32) << 10) | (mantissa >> 13);
+    let remainder = mantissa & 0x1fff;
+    let round_up = remainder > 0x1000 || (remainder == 0x1000 && half & 1 == 1);
+    // Rounding up may carry into the exponent, which is still correct
+    sign | (half + round_up as u32) as u16
+}
+
+impl VertexAttribute {
+    fn size(&self) -> usize {
+        self.components * self.attribute_type.size()
+    }
+}
+
+#[allow(dead_code)]
+impl VertexLayout {
+    pub fn new(storage: Storage) -> VertexLayout {
+        VertexLayout { attributes: vec![], storage, index_type: IndexType::U32, usage: BufferUsage::Static }
+    }
+
+    pub fn attribute(mut self, semantic: Semantic, location: u32, components: usize, attribute_type: AttributeType, normalized: bool) -> VertexLayout {
+        assert!((1..=4).contains(&components), "Vertex attributes have 1 to 4 components, not {}", components);
+        self.attributes.push(VertexAttribute { semantic, location, components, attribute_type, normalized });
+        self
+    }
+
+    pub fn index_type(mut self, index_type: IndexType) -> VertexLayout {
+        self.index_type = index_type;
+        self
+    }
+
+    pub fn usage(mut self, usage: BufferUsage) -> VertexLayout {
+        if let BufferUsage::Streaming(segments) = usage {
+            assert!(segments > 0, "A streaming buffer needs at least one segment");
+        }
+        self.usage = usage;
+        self
+    }
+
+    // Positions at location 0, colors at 1 and normals at 5, all as floats in separate buffers
+    pub fn standard() -> VertexLayout {
+        VertexLayout::new(Storage::Separate)
+            .attribute(Semantic::Position, 0, 3, AttributeType::Float, false)
+            .attribute(Semantic::Color, 1, 4, AttributeType::Float, false)
+            .attribute(Semantic::Normal, 5, 3, AttributeType::Float, false)
+    }
+
+    // Same locations as `standard`, but interleaved with half-float normals, byte colors and 16-bit
+    // indices. Takes 24 instead of 40 bytes per vertex, meant for large terrains.
+    pub fn compact() -> VertexLayout {
+        VertexLayout::new(Storage::Interleaved)
+            .attribute(Semantic::Position, 0, 3, AttributeType::Float, false)
+            .attribute(Semantic::Color, 1, 4, AttributeType::UnsignedByte, true)
+            .attribute(Semantic::Normal, 5, 3, AttributeType::HalfFloat, false)
+            .index_type(IndexType::U16)
+    }
+
+    // Which attributes go in which buffer. Attributes within an interleaved vertex start on 4 byte
+    // boundaries, which some drivers need to keep it fast. Separate buffers are tightly packed.
+    fn stream_layouts(&self) -> Vec<(Vec<(VertexAttribute, usize)>, usize)> {
+        match self.storage {
+            Storage::Separate => self.attributes.iter()
+                .map(|&attribute| (vec![(attribute, 0)], attribute.size()))
+                .collect(),
+            Storage::Interleaved => {
+                let mut attributes = vec![];
+                let mut stride = 0;
+                for &attribute in &self.attributes {
+                    attributes.push((attribute, stride));
+                    stride += (attribute.size() + 3) / 4 * 4;
+                }
+                vec![(attributes, stride)]
+            },
+        }
+    }
+}
+
+// Append the attribute of one vertex. Components the mesh doesn't have are filled in with
+// 0, or 1 for the fourth one, like OpenGL does for attributes with too few components.
+fn encode_vertex(attribute: &VertexAttribute, source: &[f32], vertex: usize, out: &mut Vec<u8>) {
+    let source_components = attribute.semantic.source_components();
+    for component in 0..attribute.components {
+        let value = match source.get(vertex * source_components + component) {
+            Some(&value) if component < source_components => value,
+            _ if component == 3 => 1.0,
+            _ => 0.0,
+        };
+        attribute.attribute_type.encode(value, attribute.normalized, out);
+    }
+}
+
+// The vertices in the range, laid out like in the stream's buffer
+fn encode_vertices(attributes: &[(VertexAttribute, usize)], stride: usize, mesh: &Mesh, vertices: Range<usize>) -> Vec<u8> {
+    let mut data = Vec::with_capacity(vertices.len() * stride);
+    for vertex in vertices {
+        let start = data.len();
+        for (attribute, offset) in attributes {
+            data.resize(start + offset, 0);
+            encode_vertex(attribute, attribute.semantic.source(mesh), vertex, &mut data);
+        }
+        data.resize(start + stride, 0);
+    }
+    data
+}
+
+unsafe fn upload(target: gl::types::GLenum, data: &[u8], usage: gl::types::GLenum) -> Buffer {
+    let buffer = Buffer::new();
+    gl::BindBuffer(target, buffer.id());
+    let pointer = if data.is_empty() { ptr::null() } else { data.as_ptr() as *const _ };
+    gl::BufferData(target, data.len() as isize, pointer, usage);
+    buffer
+}
+
+// Allocate immutable storage for `segments` copies of the data and keep it mapped for writing.
+// Coherent mapping means writes are seen by the GPU without flushing.
+unsafe fn upload_mapped(data: &[u8], segments: usize) -> (Buffer, *mut u8) {
+    let buffer = Buffer::new();
+    gl::BindBuffer(gl::ARRAY_BUFFER, buffer.id());
+    let flags = gl::MAP_WRITE_BIT | gl::MAP_PERSISTENT_BIT | gl::MAP_COHERENT_BIT;
+    let size = (data.len() * segments).max(1) as isize;
+    gl::BufferStorage(gl::ARRAY_BUFFER, size, ptr::null(), flags);
+    let mapped = gl::MapBufferRange(gl::ARRAY_BUFFER, 0, size, flags) as *mut u8;
+    assert!(!mapped.is_null(), "Failed to map a streaming vertex buffer");
+    ptr::copy_nonoverlapping(data.as_ptr(), mapped, data.len());
+    (buffer, mapped)
+}
+
+unsafe fn set_attribute_pointer(attribute: &VertexAttribute, stride: usize, offset: usize) {
+    // The normalized flag is ignored for floats
+    gl::VertexAttribPointer(
+        attribute.location,
+        attribute.components as i32,
+        attribute.attribute_type.gl_type(),
+        if attribute.normalized { gl::TRUE } else { gl::FALSE },
+        stride as i32,
+        offset as *const _,
+    );
+    gl::EnableVertexAttribArray(attribute.location);
+}
+
+impl VertexStream {
+    // Point the attributes at the given byte offset into the buffer. Expects the VAO to be bound.
+    unsafe fn bind_attributes(&self, base: usize) {
+        gl::BindBuffer(gl::ARRAY_BUFFER, self.buffer.id());
+        for (attribute, offset) in &self.attributes {
+            set_attribute_pointer(attribute, self.stride, base + offset);
+        }
+    }
+
+    fn segment_size(&self, vertex_count: usize) -> usize {
+        vertex_count * self.stride
+    }
+}
+
+impl Vao {
+    // Upload a mesh with the given layout
+    pub unsafe fn from_mesh(mesh: &Mesh, layout: &VertexLayout) -> Vao {
+        let vertex_count = mesh.vertex_count();
+        let array = VertexArray::new();
+        gl::BindVertexArray(array.id());
+
+        // Persistent mapping needs OpenGL 4.4 or ARB_buffer_storage, use glBufferSubData without it
+        let usage = match layout.usage {
+            BufferUsage::Streaming(_) if !gl::BufferStorage::is_loaded() => BufferUsage::Dynamic,
+            usage => usage,
+        };
+
+        let mut streams = vec![];
+        let mut mapped = vec![];
+        for (attributes, stride) in layout.stream_layouts() {
+            let data = encode_vertices(&attributes, stride, mesh, 0..vertex_count);
+            let buffer = match usage {
+                BufferUsage::Static => upload(gl::ARRAY_BUFFER, &data, gl::STATIC_DRAW),
+                BufferUsage::Dynamic => upload(gl::ARRAY_BUFFER, &data, gl::DYNAMIC_DRAW),
+                BufferUsage::Streaming(segments) => {
+                    let (buffer, pointer) = upload_mapped(&data, segments);
+                    mapped.push(pointer);
+                    buffer
+                },
+            };
+            let stream = VertexStream { buffer, attributes, stride };
+            stream.bind_attributes(0);
+            streams.push(stream);
+        }
+        let ring = match usage {
+            BufferUsage::Streaming(segments) => Some(Ring { mapped, segments, current: 0, fences: vec![ptr::null(); segments] }),
+            _ => None,
+        };
+
+        // The element buffer binding is part of the VAO state, so it has to be bound while the VAO is
+        let fits_u16 = vertex_count <= u16::MAX as usize + 1;
+        let (index_data, index_type) = if layout.index_type == IndexType::U16 && fits_u16 {
+            (mesh.indices.iter().flat_map(|&i| (i as u16).to_ne_bytes()).collect::<Vec<u8>>(), gl::UNSIGNED_SHORT)
+        } else {
+            (mesh.indices.iter().flat_map(|&i| i.to_ne_bytes()).collect::<Vec<u8>>(), gl::UNSIGNED_INT)
+        };
+        let index_buffer = upload(gl::ELEMENT_ARRAY_BUFFER, &index_data, gl::STATIC_DRAW);
+
+        gl::BindVertexArray(0);
+        Vao { array, streams, index_buffer, ring, vertex_count, index_count: mesh.index_count, index_type }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.array.id()
+    }
+
+    // Upload the given range of vertices from the mesh again, e.g. after changing their positions.
+    // Streaming VAOs always upload every vertex, into the next segment of the ring.
+    // The mesh must have as many vertices as when the VAO was made.
+    pub unsafe fn update(&mut self, mesh: &Mesh, vertices: Range<usize>) {
+        self.update_attributes(mesh, vertices, None);
+    }
+
+    // Like `update`, but only for buffers holding one of these attributes. With separate storage
+    // that skips the rest, e.g. to upload new colors only.
+    pub unsafe fn update_attributes(&mut self, mesh: &Mesh, vertices: Range<usize>, semantics: Option<&[Semantic]>) {
+        assert_eq!(mesh.vertex_count(), self.vertex_count, "The vertex count of a mesh can't change after it's uploaded");
+        assert!(vertices.end <= self.vertex_count, "Vertex range {:?} is out of bounds", vertices);
+        let wanted = |stream: &VertexStream| match semantics {
+            Some(semantics) => stream.attributes.iter().any(|(attribute, _)| semantics.contains(&attribute.semantic)),
+            None => true,
+        };
+
+        if let Some(ring) = &mut self.ring {
+            // Fence the segment we're leaving, it's done once the draws issued so far are
+            ring.fences[ring.current] = gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+            ring.current = (ring.current + 1) % ring.segments;
+            let fence = std::mem::replace(&mut ring.fences[ring.current], ptr::null());
+            if !fence.is_null() {
+                while gl::ClientWaitSync(fence, gl::SYNC_FLUSH_COMMANDS_BIT, 1_000_000_000) == gl::TIMEOUT_EXPIRED { }
+                gl::DeleteSync(fence);
+            }
+
+            // The new segment still holds whatever was written to it segments ago, so every stream is written in full
+            gl::BindVertexArray(self.array.id());
+            for (stream, &mapped) in self.streams.iter().zip(&ring.mapped) {
+                let data = encode_vertices(&stream.attributes, stream.stride, mesh, 0..self.vertex_count);
+                let base = ring.current * stream.segment_size(self.vertex_count);
+                ptr::copy_nonoverlapping(data.as_ptr(), mapped.add(base), data.len());
+                stream.bind_attributes(base);
+            }
+            gl::BindVertexArray(0);
+            return;
+        }
+
+        if vertices.is_empty() {
+            return;
+        }
+        for stream in self.streams.iter().filter(|stream| wanted(stream)) {
+            let data = encode_vertices(&stream.attributes, stream.stride, mesh, vertices.clone());
+            gl::BindBuffer(gl::ARRAY_BUFFER, stream.buffer.id());
+            gl::BufferSubData(gl::ARRAY_BUFFER, (vertices.start * stream.stride) as isize, data.len() as isize, data.as_ptr() as *const _);
+        }
+        gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+    }
+}
+
+impl Drop for Vao {
+    fn drop(&mut self) {
+        // The buffers are unmapped as they're deleted
+        if let Some(ring) = &self.ring {
+            for &fence in ring.fences.iter().filter(|fence| !fence.is_null()) {
+                unsafe { gl::DeleteSync(fence) };
+            }
+        }
+    }
+}
+
+// A mesh that is kept on both the CPU and the GPU. Changes made through `modify` are collected
+// and uploaded by `sync`, which only pushes the range of vertices that changed.
+pub struct DynamicMesh {
+    pub mesh : Mesh,
+    pub vao  : Vao,
+    dirty    : Option<Range<usize>>,
+}
+
+#[allow(dead_code)]
+impl DynamicMesh {
+    // The layout should have Dynamic or Streaming usage
+    pub unsafe fn new(mesh: Mesh, layout: &VertexLayout) -> DynamicMesh {
+        let vao = Vao::from_mesh(&mesh, layout);
+        DynamicMesh { mesh, vao, dirty: None }
+    }
+
+    // Change the mesh. `f` returns the range of vertices it changed, if any
+    pub fn modify<F: FnOnce(&mut Mesh) -> Option<Range<usize>>>(&mut self, f: F) {
+        if let Some(changed) = f(&mut self.mesh) {
+            self.mark_dirty(changed);
+        }
+    }
+
+    pub fn mark_dirty(&mut self, vertices: Range<usize>) {
+        self.dirty = Some(match self.dirty.take() {
+            Some(dirty) => dirty.start.min(vertices.start)..dirty.end.max(vertices.end),
+            None => vertices,
+        });
+    }
+
+    // Upload the changes made since the last sync. Call this from the render thread.
+    pub unsafe fn sync(&mut self) {
+        if let Some(dirty) = self.dirty.take() {
+            self.vao.update(&self.mesh, dirty);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(table: &[(f32, u16)]) {
+        for &(value, expected) in table {
+            assert_eq!(f32_to_f16(value), expected, "{:e} should be {:#06x}, got {:#06x}", value, expected, f32_to_f16(value));
+        }
+    }
+
+    #[test]
+    fn f16_keeps_exact_values() {
+        check(&[
+            (0.0, 0x0000),
+            (-0.0, 0x8000),
+            (1.0, 0x3c00),
+            (-2.0, 0xc000),
+            (0.5, 0x3800),
+            (65504.0, 0x7bff), // The largest finite half
+        ]);
+    }
+
+    #[test]
+    fn f16_subnormals() {
+        check(&[
+            (2f32.powi(-14), 0x0400),                  // The smallest normal half
+            (2f32.powi(-14) - 2f32.powi(-24), 0x03ff), // The largest subnormal
+            (2f32.powi(-24), 0x0001),                  // The smallest subnormal
+            (-2f32.powi(-24), 0x8001),
+            (2f32.powi(-25), 0x0000),                  // Halfway to the smallest subnormal, to even
+            (1.5 * 2f32.powi(-25), 0x0001),
+            (3.0 * 2f32.powi(-25), 0x0002),            // Halfway between 1 and 2 subnormal steps, to even
+            (1e-10, 0x0000),
+            (-1e-10, 0x8000),
+        ]);
+    }
+
+    #[test]
+    fn f16_rounds_to_nearest_even() {
+        check(&[
+            (1.0 + 2f32.powi(-11), 0x3c00),                   // Halfway, down to even
+            (1.0 + 3.0 * 2f32.powi(-11), 0x3c02),             // Halfway, up to even
+            (1.0 + 2f32.powi(-11) + 2f32.powi(-20), 0x3c01),  // Just past halfway
+            (1.0 + 2f32.powi(-11) - 2f32.powi(-20), 0x3c00),  // Just short of halfway
+            (2047.9, 0x6800),                                 // Carries into the exponent, to 2048
+        ]);
+    }
+
+    #[test]
+    fn f16_overflows_to_infinity() {
+        check(&[
+            (65519.0, 0x7bff),
+            (65520.0, 0x7c00), // Halfway to the next power of two rounds up out of range
+            (1e6, 0x7c00),
+            (-1e6, 0xfc00),
+            (f32::MAX, 0x7c00),
+            (f32::INFINITY, 0x7c00),
+            (f32::NEG_INFINITY, 0xfc00),
+        ]);
+    }
+
+    #[test]
+    fn f16_keeps_nan() {
+        for value in [f32::NAN, -f32::NAN, f32::from_bits(0x7f80_0001)] {
+            let half = f32_to_f16(value);
+            assert!(half & 0x7c00 == 0x7c00 && half & 0x03ff != 0, "{:#06x} is not a NaN", half);
+        }
+    }
+}